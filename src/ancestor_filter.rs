@@ -0,0 +1,96 @@
+// A counting Bloom filter of the ids, classes and tag names of the ancestors of the element being
+// styled. It can't say for sure that an ancestor has some name, but it can say for sure that none
+// does, which lets us reject a selector like `#sidebar a` without walking up the tree.
+//
+// https://en.wikipedia.org/wiki/Bloom_filter#Counting_Bloom_filters
+
+use crate::css::{Combinator, Selector, SimpleSelector};
+use crate::html::ElementData;
+
+// Each hash sets two of these counters, 12 bits of the hash picking each one.
+const SLOTS: usize = 1 << 12;
+
+pub(crate) struct AncestorFilter {
+    counters: Vec<u8>,
+}
+
+impl AncestorFilter {
+    pub(crate) fn new() -> Self {
+        AncestorFilter {
+            counters: vec![0; SLOTS],
+        }
+    }
+
+    // Add an element whose descendants are about to be styled.
+    pub(crate) fn push(&mut self, element: &ElementData) {
+        for hash in element_hashes(element) {
+            for slot in slots(hash) {
+                // A full counter is never decremented again, since we don't know how many
+                // elements it stands for.
+                self.counters[slot] = self.counters[slot].saturating_add(1);
+            }
+        }
+    }
+
+    // Remove an element pushed earlier, once its descendants are done.
+    pub(crate) fn pop(&mut self, element: &ElementData) {
+        for hash in element_hashes(element) {
+            for slot in slots(hash) {
+                if self.counters[slot] != u8::MAX {
+                    self.counters[slot] -= 1;
+                }
+            }
+        }
+    }
+
+    // False if some hash definitely doesn't belong to any ancestor.
+    pub(crate) fn might_contain_all(&self, hashes: &[u32]) -> bool {
+        hashes
+            .iter()
+            .all(|&hash| slots(hash).iter().all(|&slot| self.counters[slot] > 0))
+    }
+}
+
+fn slots(hash: u32) -> [usize; 2] {
+    let mask = SLOTS as u32 - 1;
+    [(hash & mask) as usize, ((hash >> 12) & mask) as usize]
+}
+
+// The hashes of the ids, classes and tag names that an element matching `selector` must have
+// among its ancestors.
+pub(crate) fn selector_hashes(selector: &Selector) -> Vec<u32> {
+    let mut hashes = Vec::new();
+    let mut selector = selector;
+    while let Selector::Complex(left, combinator, _) = selector {
+        // Siblings have the same ancestors, so in `a > b + c d` both `a` and `c` are ancestors
+        // of `d`, but `b` needn't be.
+        if matches!(combinator, Combinator::Descendant | Combinator::Child) {
+            hashes.extend(simple_selector_hashes(left.rightmost()));
+        }
+        selector = left;
+    }
+    hashes
+}
+
+fn simple_selector_hashes(selector: &SimpleSelector) -> impl Iterator<Item = u32> + '_ {
+    let id = selector.id.iter().map(|id| hash(b'#', id));
+    let classes = selector.class.iter().map(|class| hash(b'.', class));
+    let tag = selector.tag_name.iter().map(|tag| hash(b'<', tag));
+    id.chain(classes).chain(tag)
+}
+
+fn element_hashes(element: &ElementData) -> impl Iterator<Item = u32> + '_ {
+    let id = element.id().into_iter().map(|id| hash(b'#', id));
+    let classes = element.classes().into_iter().map(|class| hash(b'.', class));
+    let tag = std::iter::once(hash(b'<', &element.tag_name));
+    id.chain(classes).chain(tag)
+}
+
+// FNV-1a, with a prefix so that e.g. the id `a` and the class `a` hash differently.
+fn hash(prefix: u8, name: &str) -> u32 {
+    std::iter::once(prefix)
+        .chain(name.bytes())
+        .fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
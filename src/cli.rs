@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+
+pub(crate) const USAGE: &str = "\
+Usage: toy-browser [OPTIONS] <HTML_FILE>
+
+Options:
+  -c, --css <FILE>      Apply a stylesheet; may be given more than once
+  -w, --width <PX>      Viewport width [default: 800]
+  -H, --height <PX>     Viewport height [default: 600]
+  -o, --output <FILE>   Write output to FILE instead of stdout (required for images)
+  -m, --mode <MODE>     What to output: dom, stylesheet, style, layout or image
+                        [default: image]
+  -h, --help            Print this help";
+
+const DEFAULT_WIDTH: f32 = 800.0;
+const DEFAULT_HEIGHT: f32 = 600.0;
+const DEFAULT_IMAGE_PATH: &str = "output.png";
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum OutputMode {
+    Dom,
+    Stylesheet,
+    Style,
+    Layout,
+    Image,
+}
+
+impl TryFrom<&str> for OutputMode {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "dom" => Ok(OutputMode::Dom),
+            "stylesheet" | "css" => Ok(OutputMode::Stylesheet),
+            "style" => Ok(OutputMode::Style),
+            "layout" => Ok(OutputMode::Layout),
+            "image" => Ok(OutputMode::Image),
+            _ => Err(format!("Unknown output mode {:?}", value)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Args {
+    pub(crate) html: PathBuf,
+    pub(crate) css: Vec<PathBuf>,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+    pub(crate) output: Option<PathBuf>,
+    pub(crate) mode: OutputMode,
+    pub(crate) help: bool,
+}
+
+impl Args {
+    // Parse command-line arguments, not including the program name.
+    pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut html = None;
+        let mut css = Vec::new();
+        let mut width = DEFAULT_WIDTH;
+        let mut height = DEFAULT_HEIGHT;
+        let mut output = None;
+        let mut mode = OutputMode::Image;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`.
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || -> Result<String, String> {
+                match inline_value {
+                    Some(value) => Ok(value.to_string()),
+                    None => args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for {}", flag)),
+                }
+            };
+            match flag.as_str() {
+                "-h" | "--help" => {
+                    return Ok(Args {
+                        html: PathBuf::new(),
+                        css,
+                        width,
+                        height,
+                        output,
+                        mode,
+                        help: true,
+                    })
+                }
+                "-c" | "--css" => css.push(PathBuf::from(value()?)),
+                "-w" | "--width" => width = parse_px(&value()?)?,
+                "-H" | "--height" => height = parse_px(&value()?)?,
+                "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+                "-m" | "--mode" => mode = OutputMode::try_from(value()?.as_str())?,
+                _ if arg.starts_with('-') && arg != "-" => {
+                    return Err(format!("Unknown option {}", arg))
+                }
+                _ if html.is_some() => return Err(format!("Unexpected argument {}", arg)),
+                _ => html = Some(PathBuf::from(arg)),
+            }
+        }
+
+        let html = html.ok_or_else(|| "Missing HTML file".to_string())?;
+        if mode == OutputMode::Image && output.is_none() {
+            output = Some(PathBuf::from(DEFAULT_IMAGE_PATH));
+        }
+        Ok(Args {
+            html,
+            css,
+            width,
+            height,
+            output,
+            mode,
+            help: false,
+        })
+    }
+}
+
+// The largest viewport width or height we'll paint, which keeps the canvas to a size we can
+// allocate.
+const MAX_SIZE: f32 = 16384.0;
+
+// Parse a viewport size. The canvas is a whole number of pixels, and an image with no rows or
+// columns isn't a valid PNG, so anything below 1px is rejected.
+fn parse_px(value: &str) -> Result<f32, String> {
+    match value.trim_end_matches("px").parse::<f32>() {
+        Ok(px) if (1.0..=MAX_SIZE).contains(&px) => Ok(px),
+        Ok(px) if px > MAX_SIZE => Err(format!("Size {:?} must be at most {}px", value, MAX_SIZE)),
+        Ok(px) if px.is_finite() => Err(format!("Size {:?} must be at least 1px", value)),
+        _ => Err(format!("Invalid size {:?}", value)),
+    }
+}
@@ -1,6 +1,19 @@
 #[derive(Debug, PartialEq)]
 pub(crate) struct Stylesheet {
     pub(crate) rules: Vec<Rule>,
+    pub(crate) origin: Origin,
+}
+
+// Where a stylesheet comes from, which decides how its declarations rank in the cascade.
+// https://www.w3.org/TR/css-cascade-4/#cascading-origins
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Origin {
+    // The browser's built-in defaults.
+    UserAgent,
+    // Sheets the user gives on the command line.
+    User,
+    // Sheets belonging to the document.
+    Author,
 }
 
 #[derive(Debug, PartialEq)]
@@ -12,6 +25,22 @@ pub(crate) struct Rule {
 #[derive(Debug, PartialEq)]
 pub(crate) enum Selector {
     Simple(SimpleSelector),
+    // `left combinator right`: matches an element that matches `right` and is related to an
+    // element matching `left` as the combinator says. `a b > c` nests as `(a b) > c`.
+    Complex(Box<Selector>, Combinator, SimpleSelector),
+}
+
+// https://www.w3.org/TR/selectors-4/#combinators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Combinator {
+    // `a b`: b is a descendant of a.
+    Descendant,
+    // `a > b`: b is a child of a.
+    Child,
+    // `a + b`: b immediately follows a.
+    NextSibling,
+    // `a ~ b`: b follows a.
+    SubsequentSibling,
 }
 
 #[derive(Debug, PartialEq)]
@@ -19,34 +48,183 @@ pub(crate) struct SimpleSelector {
     pub(crate) tag_name: Option<String>,
     pub(crate) id: Option<String>,
     pub(crate) class: Vec<String>,
+    pub(crate) attributes: Vec<AttributeSelector>,
+    pub(crate) pseudo_classes: Vec<PseudoClass>,
+    // Only allowed on the last simple selector of a selector, as in `p.note::before`.
+    pub(crate) pseudo_element: Option<PseudoElement>,
+}
+
+// https://www.w3.org/TR/selectors-4/#attribute-selectors
+#[derive(Debug, PartialEq)]
+pub(crate) struct AttributeSelector {
+    pub(crate) name: String,
+    // How to compare the attribute's value, or `None` if it only has to be present.
+    pub(crate) matcher: Option<(AttributeOperator, String)>,
+    // Set by the `i` flag, as in `[type=a i]`.
+    pub(crate) case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AttributeOperator {
+    // `[a=v]`: exactly v.
+    Equals,
+    // `[a~=v]`: a whitespace-separated list containing v.
+    Includes,
+    // `[a|=v]`: v, or starting with v followed by `-`.
+    DashMatch,
+    // `[a^=v]`: starting with v.
+    Prefix,
+    // `[a$=v]`: ending with v.
+    Suffix,
+    // `[a*=v]`: containing v.
+    Substring,
+}
+
+// https://www.w3.org/TR/selectors-4/#pseudo-classes
+#[derive(Debug, PartialEq)]
+pub(crate) enum PseudoClass {
+    // `:root`: the root of the document.
+    Root,
+    // `:empty`: no element or text children.
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    // `:nth-child(An+B)`, or `:nth-child(An+B of S)` to count only the siblings matching S.
+    NthChild(Nth, Option<Vec<Selector>>),
+    // `:nth-of-type(An+B)`: counts only siblings with the same tag name.
+    NthOfType(Nth),
+    // `:not(S)`: matches none of the selectors.
+    Not(Vec<Selector>),
+    // `:is(S)`: matches any of the selectors.
+    Is(Vec<Selector>),
+    // `:where(S)`: like `:is()`, but adds nothing to specificity.
+    Where(Vec<Selector>),
+    // `:has(S)`: some element relative to this one matches.
+    Has(Vec<RelativeSelector>),
+}
+
+// https://www.w3.org/TR/css-pseudo-4/#generated-content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PseudoElement {
+    Before,
+    After,
+}
+
+// The `An+B` argument of `:nth-child()` and `:nth-of-type()`. Matches the elements at 1-based
+// positions An+B for any n >= 0.
+// https://www.w3.org/TR/css-syntax-3/#anb-microsyntax
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Nth {
+    pub(crate) a: i32,
+    pub(crate) b: i32,
+}
+
+impl Nth {
+    pub(crate) fn matches(&self, position: usize) -> bool {
+        // In i64, so that extreme values of A and B can't overflow.
+        let offset = position as i64 - self.b as i64;
+        match self.a as i64 {
+            0 => offset == 0,
+            a => offset % a == 0 && offset / a >= 0,
+        }
+    }
+}
+
+// A selector inside `:has()`, which starts with a combinator relating it to the element being
+// matched: `:has(> img)` is `Child` and `img`. Without one the combinator is `Descendant`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct RelativeSelector {
+    pub(crate) combinator: Combinator,
+    pub(crate) selector: Selector,
 }
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct Declaration {
     pub(crate) name: String,
     pub(crate) value: Value,
+    // Whether the declaration ends in `!important`.
+    pub(crate) important: bool,
 }
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) enum Value {
     Keyword(String),
     Length(f32, Unit),
     ColorValue(Color),
+    // A number without a unit, like the `2` in `counter-reset: item 2`.
+    Number(f32),
+    // A quoted string, like `"Times New Roman"`.
+    String(String),
+    // Several values, like `10px 20px` or `Arial, sans-serif`. A comma-separated list can hold
+    // space-separated lists, but not the other way round.
+    List(Vec<Value>, Separator),
+    // The value of `content`, other than `none` and `normal`: the parts of the generated text.
+    Content(Vec<ContentItem>),
     // insert more values here
 }
 
-#[derive(Debug, PartialEq)]
+// https://www.w3.org/TR/css-content-3/#content-property
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ContentItem {
+    // A quoted string.
+    String(String),
+    // `attr(name)`: the value of the element's attribute, or nothing if it doesn't have one.
+    Attr(String),
+    // `counter(name, style)`: the innermost counter with the name, in a counter style such as
+    // `decimal` or `lower-roman`.
+    Counter(String, String),
+    // `counters(name, separator, style)`: all counters with the name, outermost first.
+    Counters(String, String, String),
+}
+
+impl Value {
+    // Return the size of a length in px, or zero for non-lengths, which takes care of the
+    // unitless `0`.
+    pub fn to_px(&self) -> f32 {
+        match *self {
+            Value::Length(f, Unit::Px) => f,
+            _ => 0.0,
+        }
+    }
+
+    // A list of `items`, or the item itself if there's only one.
+    pub(crate) fn list(mut items: Vec<Value>, separator: Separator) -> Value {
+        if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Value::List(items, separator)
+        }
+    }
+
+    // The items of a space-separated list, or just this value if it isn't one.
+    pub(crate) fn space_separated(&self) -> &[Value] {
+        match self {
+            Value::List(items, Separator::Space) => items,
+            value => std::slice::from_ref(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Separator {
+    Space,
+    Comma,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) enum Unit {
     Px,
     // insert more units here
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-    a: u8,
+    pub(crate) r: u8,
+    pub(crate) g: u8,
+    pub(crate) b: u8,
+    pub(crate) a: u8,
 }
 
 impl TryFrom<String> for Color {
@@ -54,36 +232,94 @@ impl TryFrom<String> for Color {
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
         let hex = value.trim_start_matches('#'); // Remove the leading '#'
-        match hex.len() {
-            6 => {
-                // RGB format
-                let r = u8::from_str_radix(&hex[0..2], 16).map_err(|e| e.to_string())?;
-                let g = u8::from_str_radix(&hex[2..4], 16).map_err(|e| e.to_string())?;
-                let b = u8::from_str_radix(&hex[4..6], 16).map_err(|e| e.to_string())?;
-                Ok(Color { r, g, b, a: 255 })
-            }
-            8 => {
-                // RGBA format
-                let r = u8::from_str_radix(&hex[0..2], 16).map_err(|e| e.to_string())?;
-                let g = u8::from_str_radix(&hex[2..4], 16).map_err(|e| e.to_string())?;
-                let b = u8::from_str_radix(&hex[4..6], 16).map_err(|e| e.to_string())?;
-                let a = u8::from_str_radix(&hex[6..8], 16).map_err(|e| e.to_string())?;
-                Ok(Color { r, g, b, a })
-            }
-            _ => Err("Invalid color syntax".to_string()), // Invalid format
-        }
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or("Invalid color syntax")?;
+        let channels: Vec<u8> = match digits.len() {
+            // RGB and RGBA formats, with one digit per channel standing for two: `#f80` is
+            // `#ff8800`.
+            3 | 4 => digits.iter().map(|digit| digit * 17).collect(),
+            // RRGGBB and RRGGBBAA formats
+            6 | 8 => digits
+                .chunks(2)
+                .map(|pair| pair[0] * 16 + pair[1])
+                .collect(),
+            _ => return Err("Invalid color syntax".to_string()), // Invalid format
+        };
+        Ok(Color {
+            r: channels[0],
+            g: channels[1],
+            b: channels[2],
+            a: channels.get(3).copied().unwrap_or(255),
+        })
     }
 }
 
 pub type Specificity = (usize, usize, usize);
 
 impl Selector {
+    // The simple selector the element itself has to match.
+    pub(crate) fn rightmost(&self) -> &SimpleSelector {
+        match self {
+            Selector::Simple(simple) | Selector::Complex(_, _, simple) => simple,
+        }
+    }
+
+    // The pseudo-element this selector styles, if any, rather than the element itself.
+    pub(crate) fn pseudo_element(&self) -> Option<PseudoElement> {
+        self.rightmost().pseudo_element
+    }
+
     pub fn specificity(&self) -> Specificity {
         // http://www.w3.org/TR/selectors/#specificity
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
-        (a, b, c)
+        match self {
+            Selector::Simple(simple) => simple.specificity(),
+            // The specificities of all the parts add up.
+            Selector::Complex(left, _, right) => add(left.specificity(), right.specificity()),
+        }
+    }
+}
+
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        let a = self.id.iter().count();
+        let b = self.class.len() + self.attributes.len();
+        let c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        self.pseudo_classes
+            .iter()
+            .map(PseudoClass::specificity)
+            .fold((a, b, c), add)
+    }
+}
+
+impl PseudoClass {
+    // Most pseudo-classes count like a class. Those taking a selector list count as the most
+    // specific selector in it instead.
+    fn specificity(&self) -> Specificity {
+        match self {
+            PseudoClass::Not(selectors) | PseudoClass::Is(selectors) => max_specificity(selectors),
+            PseudoClass::Where(_) => (0, 0, 0),
+            PseudoClass::Has(selectors) => selectors
+                .iter()
+                .map(|relative| relative.selector.specificity())
+                .max()
+                .unwrap_or_default(),
+            PseudoClass::NthChild(_, Some(selectors)) => add((0, 1, 0), max_specificity(selectors)),
+            _ => (0, 1, 0),
+        }
     }
 }
+
+fn max_specificity(selectors: &[Selector]) -> Specificity {
+    selectors
+        .iter()
+        .map(Selector::specificity)
+        .max()
+        .unwrap_or_default()
+}
+
+fn add((a1, b1, c1): Specificity, (a2, b2, c2): Specificity) -> Specificity {
+    (a1 + a2, b1 + b2, c1 + c2)
+}
@@ -0,0 +1,399 @@
+// Splitting CSS source text into tokens.
+//
+// https://www.w3.org/TR/css-syntax-3/#tokenization
+//
+// The input isn't preprocessed as the spec describes, so that token positions are byte offsets
+// into the original text: CR and form feed are treated as newlines where it matters, and NUL is
+// replaced when it ends up in a name or string.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    // A name followed by `(`, such as `attr(`.
+    Function(String),
+    AtKeyword(String),
+    // `#name`. `is_id` says whether the name would also be a valid identifier, as id selectors
+    // need.
+    Hash { value: String, is_id: bool },
+    String(String),
+    // A string with an unescaped newline in it.
+    BadString,
+    // An unquoted `url(...)`.
+    Url(String),
+    BadUrl,
+    Delim(char),
+    Number(Number),
+    Percentage(Number),
+    Dimension(Number, String),
+    Whitespace,
+    // `<!--` and `-->`, which may surround a stylesheet in a `<style>` element.
+    Cdo,
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Number {
+    pub(crate) value: f32,
+    // Whether it was written without a fraction or exponent.
+    pub(crate) integer: bool,
+}
+
+// A token and the byte range of the input it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Spanned {
+    pub(crate) token: Token,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+// Split `input` into tokens. Comments are dropped.
+pub(crate) fn tokenize(input: &str) -> Vec<Spanned> {
+    let mut tokenizer = Tokenizer { input, pos: 0 };
+    let mut tokens = Vec::new();
+    loop {
+        tokenizer.consume_comments();
+        let start = tokenizer.pos;
+        match tokenizer.consume_token() {
+            Some(token) => tokens.push(Spanned {
+                token,
+                start,
+                end: tokenizer.pos,
+            }),
+            None => return tokens,
+        }
+    }
+}
+
+struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Tokenizer<'_> {
+    // The character `n` places ahead of the current one.
+    fn peek(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn consume_comments(&mut self) {
+        while self.input[self.pos..].starts_with("/*") {
+            match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos += 2 + end + 2,
+                None => self.pos = self.input.len(),
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-token
+    fn consume_token(&mut self) -> Option<Token> {
+        let c = self.consume()?;
+        let token = match c {
+            c if is_whitespace(c) => {
+                while self.peek(0).is_some_and(is_whitespace) {
+                    self.consume();
+                }
+                Token::Whitespace
+            }
+            '"' | '\'' => self.consume_string(c),
+            '#' if self.peek(0).is_some_and(is_name) || self.starts_escape(0) => {
+                let is_id = self.starts_ident(0);
+                Token::Hash {
+                    value: self.consume_name(),
+                    is_id,
+                }
+            }
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            '{' => Token::LeftBrace,
+            '}' => Token::RightBrace,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            ';' => Token::Semicolon,
+            '+' | '.' if self.starts_number(-1) => self.consume_numeric(c),
+            '-' if self.starts_number(-1) => self.consume_numeric(c),
+            '-' if self.peek(0) == Some('-') && self.peek(1) == Some('>') => {
+                self.consume();
+                self.consume();
+                Token::Cdc
+            }
+            '-' if self.starts_ident(-1) => self.consume_ident_like(c),
+            '<' if self.input[self.pos..].starts_with("!--") => {
+                self.pos += 3;
+                Token::Cdo
+            }
+            '@' if self.starts_ident(0) => Token::AtKeyword(self.consume_name()),
+            '\\' if self.starts_escape(-1) => self.consume_ident_like(c),
+            '0'..='9' => self.consume_numeric(c),
+            c if is_name_start(c) => self.consume_ident_like(c),
+            c => Token::Delim(c),
+        };
+        Some(token)
+    }
+
+    // The characters starting `offset` places from the current one, where -1 is the character
+    // just consumed.
+    fn window(&self, offset: isize) -> [Option<char>; 3] {
+        let at = |n: isize| match offset + n {
+            -1 => self.input[..self.pos].chars().next_back(),
+            n => self.peek(n as usize),
+        };
+        [at(0), at(1), at(2)]
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#starts-with-a-valid-escape
+    fn starts_escape(&self, offset: isize) -> bool {
+        let [first, second, _] = self.window(offset);
+        first == Some('\\') && !second.is_some_and(is_newline)
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#would-start-an-identifier
+    fn starts_ident(&self, offset: isize) -> bool {
+        match self.window(offset) {
+            [Some('-'), Some(c), _] if is_name_start(c) || c == '-' => true,
+            [Some('-'), Some('\\'), _] => self.starts_escape(offset + 1),
+            [Some('\\'), ..] => self.starts_escape(offset),
+            [Some(c), ..] => is_name_start(c),
+            _ => false,
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#starts-with-a-number
+    fn starts_number(&self, offset: isize) -> bool {
+        let digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+        match self.window(offset) {
+            [Some('+' | '-'), Some('.'), third] => digit(third),
+            [Some('+' | '-'), second, _] => digit(second),
+            [Some('.'), second, _] => digit(second),
+            [first, ..] => digit(first),
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-an-escaped-code-point
+    // Called after the backslash.
+    fn consume_escape(&mut self) -> char {
+        let Some(c) = self.consume() else {
+            return '\u{fffd}';
+        };
+        if !c.is_ascii_hexdigit() {
+            return c;
+        }
+        let mut hex = c.to_string();
+        while hex.len() < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.extend(self.consume());
+        }
+        if self.input[self.pos..].starts_with("\r\n") {
+            self.pos += 2;
+        } else if self.peek(0).is_some_and(is_whitespace) {
+            self.consume();
+        }
+        let code = u32::from_str_radix(&hex, 16).unwrap_or(0);
+        match char::from_u32(code) {
+            Some('\0') | None => '\u{fffd}',
+            Some(c) => c,
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-name
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some('\0') => {
+                    self.consume();
+                    name.push('\u{fffd}');
+                }
+                Some(c) if is_name(c) => name.extend(self.consume()),
+                Some('\\') if self.starts_escape(0) => {
+                    self.consume();
+                    name.push(self.consume_escape());
+                }
+                _ => return name,
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-string-token
+    // Called after the opening quote.
+    fn consume_string(&mut self, quote: char) -> Token {
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None => return Token::String(value),
+                Some(c) if c == quote => {
+                    self.consume();
+                    return Token::String(value);
+                }
+                // The newline is left to start the next token.
+                Some(c) if is_newline(c) => return Token::BadString,
+                Some('\\') => {
+                    self.consume();
+                    match self.peek(0) {
+                        None => {}
+                        // An escaped newline continues the string on the next line.
+                        Some('\r') if self.peek(1) == Some('\n') => self.pos += 2,
+                        Some(c) if is_newline(c) => {
+                            self.consume();
+                        }
+                        Some(_) => value.push(self.consume_escape()),
+                    }
+                }
+                Some('\0') => {
+                    self.consume();
+                    value.push('\u{fffd}');
+                }
+                Some(_) => value.extend(self.consume()),
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-numeric-token
+    // Called after the first character of the number.
+    fn consume_numeric(&mut self, first: char) -> Token {
+        let start = self.pos - first.len_utf8();
+        let digits = |tokenizer: &mut Self| {
+            while tokenizer.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+                tokenizer.consume();
+            }
+        };
+        let mut integer = true;
+        if first != '.' {
+            digits(self);
+        }
+        if first == '.'
+            || (self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            if first != '.' {
+                self.consume();
+            }
+            digits(self);
+            integer = false;
+        }
+        if matches!(self.peek(0), Some('e' | 'E')) {
+            let sign = matches!(self.peek(1), Some('+' | '-'));
+            let exponent = if sign { self.peek(2) } else { self.peek(1) };
+            if exponent.is_some_and(|c| c.is_ascii_digit()) {
+                self.consume();
+                if sign {
+                    self.consume();
+                }
+                digits(self);
+                integer = false;
+            }
+        }
+        let number = Number {
+            value: self.input[start..self.pos].parse().unwrap_or(0.0),
+            integer,
+        };
+        if self.starts_ident(0) {
+            Token::Dimension(number, self.consume_name())
+        } else if self.peek(0) == Some('%') {
+            self.consume();
+            Token::Percentage(number)
+        } else {
+            Token::Number(number)
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-ident-like-token
+    // Called after the first character of the name.
+    fn consume_ident_like(&mut self, first: char) -> Token {
+        self.pos -= first.len_utf8();
+        let name = self.consume_name();
+        if self.peek(0) != Some('(') {
+            return Token::Ident(name);
+        }
+        self.consume();
+        if !name.eq_ignore_ascii_case("url") {
+            return Token::Function(name);
+        }
+        // `url("...")` is an ordinary function taking a string.
+        let rest = self.input[self.pos..].trim_start_matches(is_whitespace);
+        if rest.starts_with(['"', '\'']) {
+            return Token::Function(name);
+        }
+        self.consume_url()
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-url-token
+    // Called after `url(`.
+    fn consume_url(&mut self) -> Token {
+        let mut value = String::new();
+        while self.peek(0).is_some_and(is_whitespace) {
+            self.consume();
+        }
+        loop {
+            match self.consume() {
+                None | Some(')') => return Token::Url(value),
+                Some(c) if is_whitespace(c) => {
+                    while self.peek(0).is_some_and(is_whitespace) {
+                        self.consume();
+                    }
+                    if matches!(self.peek(0), None | Some(')')) {
+                        self.consume();
+                        return Token::Url(value);
+                    }
+                    return self.consume_bad_url();
+                }
+                Some('"' | '\'' | '(') => return self.consume_bad_url(),
+                Some(c) if is_non_printable(c) => return self.consume_bad_url(),
+                Some('\\') if self.starts_escape(-1) => value.push(self.consume_escape()),
+                Some('\\') => return self.consume_bad_url(),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-remnants-of-bad-url
+    fn consume_bad_url(&mut self) -> Token {
+        loop {
+            match self.consume() {
+                None | Some(')') => return Token::BadUrl,
+                Some('\\') if self.starts_escape(-1) => {
+                    self.consume_escape();
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+fn is_newline(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\x0c')
+}
+
+fn is_whitespace(c: char) -> bool {
+    is_newline(c) || c == ' ' || c == '\t'
+}
+
+// https://www.w3.org/TR/css-syntax-3/#ident-start-code-point
+// NUL counts too, as it becomes U+FFFD.
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii() || c == '\0'
+}
+
+// https://www.w3.org/TR/css-syntax-3/#ident-code-point
+fn is_name(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+// https://www.w3.org/TR/css-syntax-3/#non-printable-code-point
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\x08' | '\x0b' | '\x0e'..='\x1f' | '\x7f')
+}
@@ -1,13 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 
 pub struct Node {
     pub(crate) children: Vec<Node>,
-    node_type: NodeType,
+    pub(crate) node_type: NodeType,
 }
 
 impl Node {
-    fn to_string(&self) -> String {
+    // The tag name if this is an element, otherwise `None`.
+    pub(crate) fn tag_name(&self) -> Option<&str> {
+        match &self.node_type {
+            NodeType::Element(data) => Some(&data.tag_name),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn summary(&self) -> String {
         match &self.node_type {
             NodeType::Text(text) => {
                 format!("Text({:?})", text)
@@ -15,11 +23,17 @@ impl Node {
             NodeType::Element(data) => {
                 format!("Element({:?})", data)
             }
+            NodeType::Comment(data) => {
+                format!("Comment({:?})", data)
+            }
+            NodeType::Doctype(data) => {
+                format!("Doctype({:?})", data)
+            }
         }
     }
     fn pretty_print(&self, prefix: String, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}├──{:?}\n", prefix, self.to_string()).expect("Failed to write");
-        for (_, child) in self.children.iter().enumerate() {
+        writeln!(f, "{}├──{:?}", prefix, self.summary()).expect("Failed to write");
+        for child in self.children.iter() {
             let next_prefix = format!("{}   ", prefix);
             child.pretty_print(next_prefix, f).expect("Failed to write");
         }
@@ -53,15 +67,72 @@ impl PartialEq for Node {
 impl Eq for Node {}
 
 #[derive(PartialEq, Eq)]
-enum NodeType {
+pub(crate) enum NodeType {
     Text(String),
     Element(ElementData),
+    Comment(String),
+    Doctype(DoctypeData),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DoctypeData {
+    pub(crate) name: String,
+    pub(crate) public_id: String,
+    pub(crate) system_id: String,
+}
+
+// https://dom.spec.whatwg.org/#concept-document-mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QuirksMode {
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+// A parsed document: the DOCTYPE and any comments outside the `html` element, plus the
+// `html` element itself.
+pub(crate) struct Document {
+    pub(crate) children: Vec<Node>,
+    pub(crate) mode: QuirksMode,
+}
+
+impl Document {
+    // The root `html` element.
+    pub(crate) fn document_element(&self) -> &Node {
+        self.children
+            .iter()
+            .find(|node| node.tag_name().is_some())
+            .expect("a document always has an html element")
+    }
+}
+
+impl Debug for Document {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Document({:?})", self.mode)?;
+        for child in self.children.iter() {
+            child.pretty_print("".to_string(), f)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(PartialEq, Eq)]
-struct ElementData {
-    tag_name: String,
-    attrs: AttrMap,
+pub(crate) struct ElementData {
+    pub(crate) tag_name: String,
+    pub(crate) attrs: AttrMap,
+}
+
+impl ElementData {
+    pub(crate) fn id(&self) -> Option<&String> {
+        self.attrs.attrs.get("id")
+    }
+
+    pub(crate) fn classes(&self) -> HashSet<&str> {
+        match self.attrs.attrs.get("class") {
+            Some(classlist) => classlist.split_whitespace().collect(),
+            None => HashSet::new(),
+        }
+    }
 }
 
 impl Debug for ElementData {
@@ -75,6 +146,14 @@ pub(crate) struct AttrMap {
     pub(crate) attrs: HashMap<String, String>,
 }
 
+impl From<Vec<(String, String)>> for AttrMap {
+    fn from(attrs: Vec<(String, String)>) -> Self {
+        AttrMap {
+            attrs: attrs.into_iter().collect(),
+        }
+    }
+}
+
 impl Debug for AttrMap {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         for (key, value) in self.attrs.iter() {
@@ -97,3 +176,10 @@ pub fn elem(tag_name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
         node_type: NodeType::Element(ElementData { tag_name, attrs }),
     }
 }
+
+pub fn comment(data: String) -> Node {
+    Node {
+        children: Vec::new(),
+        node_type: NodeType::Comment(data),
+    }
+}
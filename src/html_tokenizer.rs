@@ -0,0 +1,1190 @@
+// An HTML tokenizer following the state machine in the HTML Living Standard:
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+//
+// The tokenizer never fails. Malformed markup is a "parse error" in spec terms, but every input
+// still produces a well-defined stream of tokens, which is what browsers do.
+
+use crate::entities;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    // A run of character tokens. Adjacent characters are coalesced into a single token.
+    Text(String),
+    Eof,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Doctype {
+    pub(crate) name: Option<String>,
+    pub(crate) public_id: Option<String>,
+    pub(crate) system_id: Option<String>,
+    pub(crate) force_quirks: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Tag {
+    pub(crate) name: String,
+    // Attributes in source order. Duplicate names are dropped, keeping the first.
+    pub(crate) attrs: Vec<(String, String)>,
+    pub(crate) self_closing: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    RcdataLessThanSign,
+    RcdataEndTagOpen,
+    RcdataEndTagName,
+    RawtextLessThanSign,
+    RawtextEndTagOpen,
+    RawtextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CharacterReference,
+    NamedCharacterReference,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+}
+
+pub(crate) struct Tokenizer<'a> {
+    input: &'a str,
+    // Byte offset of the next character to consume.
+    pos: usize,
+    // Length in bytes of the last consumed character, so that it can be reconsumed.
+    last_len: usize,
+    state: State,
+    // The state to go back to once a character reference has been consumed.
+    return_state: State,
+    pending: VecDeque<Token>,
+    text: String,
+    // The tag, comment or DOCTYPE currently being built.
+    tag: Tag,
+    is_end_tag: bool,
+    attr_name: String,
+    attr_value: String,
+    comment: String,
+    doctype: Doctype,
+    temporary_buffer: String,
+    character_reference_code: u32,
+    // The name of the last start tag emitted, which an end tag must match to close a raw text
+    // element.
+    last_start_tag: String,
+    // Whether `<![CDATA[` starts a CDATA section. That is only the case in SVG and MathML
+    // content; in HTML it is a bogus comment.
+    cdata_allowed: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Tokenizer {
+            input,
+            pos: 0,
+            last_len: 0,
+            state: State::Data,
+            return_state: State::Data,
+            pending: VecDeque::new(),
+            text: String::new(),
+            tag: Tag::default(),
+            is_end_tag: false,
+            attr_name: String::new(),
+            attr_value: String::new(),
+            comment: String::new(),
+            doctype: Doctype::default(),
+            temporary_buffer: String::new(),
+            character_reference_code: 0,
+            last_start_tag: String::new(),
+            cdata_allowed: false,
+        }
+    }
+
+    // Switch to another state. The tree builder uses this to read the contents of elements such
+    // as `script` and `title` as text.
+    pub(crate) fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    // Called by the tree builder, which knows whether the current node is foreign content.
+    pub(crate) fn set_cdata_allowed(&mut self, allowed: bool) {
+        self.cdata_allowed = allowed;
+    }
+
+    // Return the next token. After the end of the input this keeps returning `Token::Eof`.
+    pub(crate) fn next_token(&mut self) -> Token {
+        loop {
+            if let Some(token) = self.pending.pop_front() {
+                return token;
+            }
+            self.step();
+        }
+    }
+
+    // Consume the next input character, or return `None` at the end of the input.
+    fn consume(&mut self) -> Option<char> {
+        let c = self.input[self.pos..].chars().next();
+        self.last_len = c.map_or(0, char::len_utf8);
+        self.pos += self.last_len;
+        c
+    }
+
+    // Put the last consumed character back and continue in `state`.
+    fn reconsume_in(&mut self, state: State) {
+        self.pos -= self.last_len;
+        self.last_len = 0;
+        self.state = state;
+    }
+
+    // Do the next input characters match `s`, ignoring ASCII case?
+    fn next_chars_match(&self, s: &str) -> bool {
+        self.input[self.pos..]
+            .get(..s.len())
+            .is_some_and(|next| next.eq_ignore_ascii_case(s))
+    }
+
+    fn emit(&mut self, token: Token) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            self.pending.push_back(Token::Text(text));
+        }
+        self.pending.push_back(token);
+    }
+
+    fn emit_char(&mut self, c: char) {
+        self.text.push(c);
+    }
+
+    fn emit_eof(&mut self) {
+        self.emit(Token::Eof);
+    }
+
+    fn start_tag(&mut self, is_end_tag: bool) {
+        self.tag = Tag::default();
+        self.is_end_tag = is_end_tag;
+    }
+
+    fn start_attribute(&mut self) {
+        self.finish_attribute();
+        self.attr_name.clear();
+        self.attr_value.clear();
+    }
+
+    // Add the attribute being built to the current tag, unless the tag already has one with the
+    // same name.
+    fn finish_attribute(&mut self) {
+        if self.attr_name.is_empty() {
+            return;
+        }
+        let name = std::mem::take(&mut self.attr_name);
+        let value = std::mem::take(&mut self.attr_value);
+        if !self.tag.attrs.iter().any(|(existing, _)| *existing == name) {
+            self.tag.attrs.push((name, value));
+        }
+    }
+
+    fn emit_tag(&mut self) {
+        self.finish_attribute();
+        let tag = std::mem::take(&mut self.tag);
+        if self.is_end_tag {
+            self.emit(Token::EndTag(tag));
+        } else {
+            self.last_start_tag.clone_from(&tag.name);
+            self.emit(Token::StartTag(tag));
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#appropriate-end-tag-token
+    fn is_appropriate_end_tag(&self) -> bool {
+        !self.last_start_tag.is_empty() && self.tag.name == self.last_start_tag
+    }
+
+    // The "less-than sign" states of RCDATA and RAWTEXT: `</` may start an end tag.
+    fn text_less_than_sign(&mut self, end_tag_open: State, text_state: State) {
+        match self.consume() {
+            Some('/') => {
+                self.temporary_buffer.clear();
+                self.state = end_tag_open;
+            }
+            _ => {
+                self.emit_char('<');
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    // The "end tag open" states of RCDATA, RAWTEXT and script data.
+    fn text_end_tag_open(&mut self, end_tag_name: State, text_state: State) {
+        match self.consume() {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.start_tag(true);
+                self.reconsume_in(end_tag_name);
+            }
+            _ => {
+                self.text.push_str("</");
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    // The "end tag name" states of RCDATA, RAWTEXT and script data. Only an end tag matching
+    // the element being read closes it; anything else is text.
+    fn text_end_tag_name(&mut self, text_state: State) {
+        let appropriate = self.is_appropriate_end_tag();
+        match self.consume() {
+            Some('\t' | '\n' | '\x0C' | ' ') if appropriate => {
+                self.state = State::BeforeAttributeName
+            }
+            Some('/') if appropriate => self.state = State::SelfClosingStartTag,
+            Some('>') if appropriate => {
+                self.state = State::Data;
+                self.emit_tag();
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.tag.name.push(c.to_ascii_lowercase());
+                self.temporary_buffer.push(c);
+            }
+            _ => {
+                self.text.push_str("</");
+                let buffer = std::mem::take(&mut self.temporary_buffer);
+                self.text.push_str(&buffer);
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    // The "double escape start" and "double escape end" states: a `script` tag inside an
+    // escaped `<!--` section switches between `escaped` and `double_escaped`.
+    fn script_data_double_escape(&mut self, script: State, otherwise: State) {
+        match self.consume() {
+            Some(c @ ('\t' | '\n' | '\x0C' | ' ' | '/' | '>')) => {
+                self.state = if self.temporary_buffer == "script" {
+                    script
+                } else {
+                    otherwise
+                };
+                self.emit_char(c);
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.temporary_buffer.push(c.to_ascii_lowercase());
+                self.emit_char(c);
+            }
+            _ => self.reconsume_in(otherwise),
+        }
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_doctype(&mut self) {
+        let doctype = std::mem::take(&mut self.doctype);
+        self.emit(Token::Doctype(doctype));
+    }
+
+    fn emit_doctype_force_quirks(&mut self) {
+        self.doctype.force_quirks = true;
+        self.emit_doctype();
+    }
+
+    // Are we consuming a character reference inside an attribute value?
+    fn in_attribute(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
+
+    // Append the temporary buffer to the attribute value or the text, depending on where the
+    // character reference appeared.
+    fn flush_code_points(&mut self) {
+        let buffer = std::mem::take(&mut self.temporary_buffer);
+        if self.in_attribute() {
+            self.attr_value.push_str(&buffer);
+        } else {
+            self.text.push_str(&buffer);
+        }
+    }
+
+    // Run the state machine for one input character.
+    fn step(&mut self) {
+        match self.state {
+            State::Data => match self.consume() {
+                Some('&') => {
+                    self.return_state = State::Data;
+                    self.state = State::CharacterReference;
+                }
+                Some('<') => self.state = State::TagOpen,
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rcdata => match self.consume() {
+                Some('&') => {
+                    self.return_state = State::Rcdata;
+                    self.state = State::CharacterReference;
+                }
+                Some('<') => self.state = State::RcdataLessThanSign,
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rawtext => match self.consume() {
+                Some('<') => self.state = State::RawtextLessThanSign,
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptData => match self.consume() {
+                Some('<') => self.state = State::ScriptDataLessThanSign,
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Plaintext => match self.consume() {
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match self.consume() {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(false);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('?') => {
+                    self.comment.clear();
+                    self.reconsume_in(State::BogusComment);
+                }
+                None => {
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::Data);
+                }
+            },
+            State::EndTagOpen => match self.consume() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(true);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('>') => self.state = State::Data,
+                None => {
+                    self.text.push_str("</");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.clear();
+                    self.reconsume_in(State::BogusComment);
+                }
+            },
+            State::TagName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => self.tag.name.push('\u{FFFD}'),
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => self.emit_eof(),
+            },
+            State::BeforeAttributeName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/' | '>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => {
+                    self.start_attribute();
+                    self.attr_name.push('=');
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::AttributeName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ' | '/' | '>') | None => {
+                    self.reconsume_in(State::AfterAttributeName)
+                }
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => self.attr_name.push('\u{FFFD}'),
+                Some(c) => self.attr_name.push(c.to_ascii_lowercase()),
+            },
+            State::AfterAttributeName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => self.emit_eof(),
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::BeforeAttributeValue => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    // Missing attribute value.
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                _ => self.reconsume_in(State::AttributeValueUnquoted),
+            },
+            State::AttributeValueDoubleQuoted => match self.consume() {
+                Some('"') => self.state = State::AfterAttributeValueQuoted,
+                Some('&') => {
+                    self.return_state = State::AttributeValueDoubleQuoted;
+                    self.state = State::CharacterReference;
+                }
+                Some('\0') => self.attr_value.push('\u{FFFD}'),
+                Some(c) => self.attr_value.push(c),
+                None => self.emit_eof(),
+            },
+            State::AttributeValueSingleQuoted => match self.consume() {
+                Some('\'') => self.state = State::AfterAttributeValueQuoted,
+                Some('&') => {
+                    self.return_state = State::AttributeValueSingleQuoted;
+                    self.state = State::CharacterReference;
+                }
+                Some('\0') => self.attr_value.push('\u{FFFD}'),
+                Some(c) => self.attr_value.push(c),
+                None => self.emit_eof(),
+            },
+            State::AttributeValueUnquoted => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('&') => {
+                    self.return_state = State::AttributeValueUnquoted;
+                    self.state = State::CharacterReference;
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => self.attr_value.push('\u{FFFD}'),
+                Some(c) => self.attr_value.push(c),
+                None => self.emit_eof(),
+            },
+            State::AfterAttributeValueQuoted => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => self.emit_eof(),
+                Some(_) => self.reconsume_in(State::BeforeAttributeName),
+            },
+            State::SelfClosingStartTag => match self.consume() {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => self.emit_eof(),
+                Some(_) => self.reconsume_in(State::BeforeAttributeName),
+            },
+            State::RcdataLessThanSign => {
+                self.text_less_than_sign(State::RcdataEndTagOpen, State::Rcdata)
+            }
+            State::RcdataEndTagOpen => {
+                self.text_end_tag_open(State::RcdataEndTagName, State::Rcdata)
+            }
+            State::RcdataEndTagName => self.text_end_tag_name(State::Rcdata),
+            State::RawtextLessThanSign => {
+                self.text_less_than_sign(State::RawtextEndTagOpen, State::Rawtext)
+            }
+            State::RawtextEndTagOpen => {
+                self.text_end_tag_open(State::RawtextEndTagName, State::Rawtext)
+            }
+            State::RawtextEndTagName => self.text_end_tag_name(State::Rawtext),
+            State::ScriptDataLessThanSign => match self.consume() {
+                Some('/') => {
+                    self.temporary_buffer.clear();
+                    self.state = State::ScriptDataEndTagOpen;
+                }
+                Some('!') => {
+                    self.state = State::ScriptDataEscapeStart;
+                    self.text.push_str("<!");
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptData);
+                }
+            },
+            State::ScriptDataEndTagOpen => {
+                self.text_end_tag_open(State::ScriptDataEndTagName, State::ScriptData)
+            }
+            State::ScriptDataEndTagName => self.text_end_tag_name(State::ScriptData),
+            State::ScriptDataEscapeStart => match self.consume() {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapeStartDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume_in(State::ScriptData),
+            },
+            State::ScriptDataEscapeStartDash => match self.consume() {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume_in(State::ScriptData),
+            },
+            State::ScriptDataEscaped => match self.consume() {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptDataEscapedDash | State::ScriptDataEscapedDashDash => {
+                match self.consume() {
+                    Some('-') => {
+                        self.state = State::ScriptDataEscapedDashDash;
+                        self.emit_char('-');
+                    }
+                    Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                    // `-->` ends the escaped section.
+                    Some('>') if self.state == State::ScriptDataEscapedDashDash => {
+                        self.state = State::ScriptData;
+                        self.emit_char('>');
+                    }
+                    Some('\0') => {
+                        self.state = State::ScriptDataEscaped;
+                        self.emit_char('\u{FFFD}');
+                    }
+                    Some(c) => {
+                        self.state = State::ScriptDataEscaped;
+                        self.emit_char(c);
+                    }
+                    None => self.emit_eof(),
+                }
+            }
+            State::ScriptDataEscapedLessThanSign => match self.consume() {
+                Some('/') => {
+                    self.temporary_buffer.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temporary_buffer.clear();
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataDoubleEscapeStart);
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => {
+                self.text_end_tag_open(State::ScriptDataEscapedEndTagName, State::ScriptDataEscaped)
+            }
+            State::ScriptDataEscapedEndTagName => self.text_end_tag_name(State::ScriptDataEscaped),
+            State::ScriptDataDoubleEscapeStart => self.script_data_double_escape(
+                State::ScriptDataDoubleEscaped,
+                State::ScriptDataEscaped,
+            ),
+            State::ScriptDataDoubleEscaped => match self.consume() {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::ScriptDataDoubleEscapedDash | State::ScriptDataDoubleEscapedDashDash => {
+                match self.consume() {
+                    Some('-') => {
+                        self.state = State::ScriptDataDoubleEscapedDashDash;
+                        self.emit_char('-');
+                    }
+                    Some('<') => {
+                        self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                        self.emit_char('<');
+                    }
+                    Some('>') if self.state == State::ScriptDataDoubleEscapedDashDash => {
+                        self.state = State::ScriptData;
+                        self.emit_char('>');
+                    }
+                    Some('\0') => {
+                        self.state = State::ScriptDataDoubleEscaped;
+                        self.emit_char('\u{FFFD}');
+                    }
+                    Some(c) => {
+                        self.state = State::ScriptDataDoubleEscaped;
+                        self.emit_char(c);
+                    }
+                    None => self.emit_eof(),
+                }
+            }
+            State::ScriptDataDoubleEscapedLessThanSign => match self.consume() {
+                Some('/') => {
+                    self.temporary_buffer.clear();
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    self.emit_char('/');
+                }
+                _ => self.reconsume_in(State::ScriptDataDoubleEscaped),
+            },
+            State::ScriptDataDoubleEscapeEnd => self.script_data_double_escape(
+                State::ScriptDataEscaped,
+                State::ScriptDataDoubleEscaped,
+            ),
+            State::BogusComment => match self.consume() {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some('\0') => self.comment.push('\u{FFFD}'),
+                Some(c) => self.comment.push(c),
+            },
+            State::MarkupDeclarationOpen => {
+                self.comment.clear();
+                if self.next_chars_match("--") {
+                    self.pos += 2;
+                    self.state = State::CommentStart;
+                } else if self.next_chars_match("doctype") {
+                    self.pos += "doctype".len();
+                    self.state = State::Doctype;
+                } else if self.input[self.pos..].starts_with("[CDATA[") {
+                    self.pos += "[CDATA[".len();
+                    if self.cdata_allowed {
+                        self.state = State::CdataSection;
+                    } else {
+                        // Keep the contents as a comment, as browsers do.
+                        self.comment.push_str("[CDATA[");
+                        self.state = State::BogusComment;
+                    }
+                } else {
+                    self.state = State::BogusComment;
+                }
+            }
+            State::CommentStart => match self.consume() {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    // Abrupt closing of empty comment.
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentStartDash => match self.consume() {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::Comment => match self.consume() {
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => self.comment.push('\u{FFFD}'),
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentEndDash => match self.consume() {
+                Some('-') => self.state = State::CommentEnd,
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::CommentEnd => match self.consume() {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::CommentEndBang => match self.consume() {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::Doctype => {
+                self.doctype = Doctype::default();
+                match self.consume() {
+                    Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeDoctypeName,
+                    None => {
+                        self.emit_doctype_force_quirks();
+                        self.emit_eof();
+                    }
+                    _ => self.reconsume_in(State::BeforeDoctypeName),
+                }
+            }
+            State::BeforeDoctypeName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype_force_quirks();
+                }
+                None => {
+                    self.emit_doctype_force_quirks();
+                    self.emit_eof();
+                }
+                Some(c) => {
+                    let c = if c == '\0' { '\u{FFFD}' } else { c };
+                    self.doctype.name = Some(c.to_ascii_lowercase().to_string());
+                    self.state = State::DoctypeName;
+                }
+            },
+            State::DoctypeName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.emit_doctype_force_quirks();
+                    self.emit_eof();
+                }
+                Some(c) => {
+                    let c = if c == '\0' { '\u{FFFD}' } else { c };
+                    if let Some(name) = &mut self.doctype.name {
+                        name.push(c.to_ascii_lowercase());
+                    }
+                }
+            },
+            State::AfterDoctypeName => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.emit_doctype_force_quirks();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.reconsume_in(State::AfterDoctypeName);
+                    if self.next_chars_match("public") {
+                        self.pos += "public".len();
+                        self.state = State::AfterDoctypePublicKeyword;
+                    } else if self.next_chars_match("system") {
+                        self.pos += "system".len();
+                        self.state = State::AfterDoctypeSystemKeyword;
+                    } else {
+                        self.doctype.force_quirks = true;
+                        self.state = State::BogusDoctype;
+                    }
+                }
+            },
+            State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier => {
+                match self.consume() {
+                    Some('\t' | '\n' | '\x0C' | ' ') => {
+                        self.state = State::BeforeDoctypePublicIdentifier
+                    }
+                    Some('"') => {
+                        self.doctype.public_id = Some(String::new());
+                        self.state = State::DoctypePublicIdentifierDoubleQuoted;
+                    }
+                    Some('\'') => {
+                        self.doctype.public_id = Some(String::new());
+                        self.state = State::DoctypePublicIdentifierSingleQuoted;
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype_force_quirks();
+                    }
+                    None => {
+                        self.emit_doctype_force_quirks();
+                        self.emit_eof();
+                    }
+                    Some(_) => {
+                        self.doctype.force_quirks = true;
+                        self.reconsume_in(State::BogusDoctype);
+                    }
+                }
+            }
+            State::DoctypePublicIdentifierDoubleQuoted
+            | State::DoctypePublicIdentifierSingleQuoted => {
+                let quote = if self.state == State::DoctypePublicIdentifierDoubleQuoted {
+                    '"'
+                } else {
+                    '\''
+                };
+                match self.consume() {
+                    Some(c) if c == quote => self.state = State::AfterDoctypePublicIdentifier,
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype_force_quirks();
+                    }
+                    None => {
+                        self.emit_doctype_force_quirks();
+                        self.emit_eof();
+                    }
+                    Some(c) => {
+                        let c = if c == '\0' { '\u{FFFD}' } else { c };
+                        if let Some(id) = &mut self.doctype.public_id {
+                            id.push(c);
+                        }
+                    }
+                }
+            }
+            State::AfterDoctypePublicIdentifier
+            | State::BetweenDoctypePublicAndSystemIdentifiers => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {
+                    self.state = State::BetweenDoctypePublicAndSystemIdentifiers
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('"') => {
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                None => {
+                    self.emit_doctype_force_quirks();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.doctype.force_quirks = true;
+                    self.reconsume_in(State::BogusDoctype);
+                }
+            },
+            State::AfterDoctypeSystemKeyword | State::BeforeDoctypeSystemIdentifier => {
+                match self.consume() {
+                    Some('\t' | '\n' | '\x0C' | ' ') => {
+                        self.state = State::BeforeDoctypeSystemIdentifier
+                    }
+                    Some('"') => {
+                        self.doctype.system_id = Some(String::new());
+                        self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                    }
+                    Some('\'') => {
+                        self.doctype.system_id = Some(String::new());
+                        self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                    }
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype_force_quirks();
+                    }
+                    None => {
+                        self.emit_doctype_force_quirks();
+                        self.emit_eof();
+                    }
+                    Some(_) => {
+                        self.doctype.force_quirks = true;
+                        self.reconsume_in(State::BogusDoctype);
+                    }
+                }
+            }
+            State::DoctypeSystemIdentifierDoubleQuoted
+            | State::DoctypeSystemIdentifierSingleQuoted => {
+                let quote = if self.state == State::DoctypeSystemIdentifierDoubleQuoted {
+                    '"'
+                } else {
+                    '\''
+                };
+                match self.consume() {
+                    Some(c) if c == quote => self.state = State::AfterDoctypeSystemIdentifier,
+                    Some('>') => {
+                        self.state = State::Data;
+                        self.emit_doctype_force_quirks();
+                    }
+                    None => {
+                        self.emit_doctype_force_quirks();
+                        self.emit_eof();
+                    }
+                    Some(c) => {
+                        let c = if c == '\0' { '\u{FFFD}' } else { c };
+                        if let Some(id) = &mut self.doctype.system_id {
+                            id.push(c);
+                        }
+                    }
+                }
+            }
+            State::AfterDoctypeSystemIdentifier => match self.consume() {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.emit_doctype_force_quirks();
+                    self.emit_eof();
+                }
+                // Unexpected character after the system identifier; this does not set
+                // force-quirks.
+                Some(_) => self.reconsume_in(State::BogusDoctype),
+            },
+            State::BogusDoctype => match self.consume() {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+                Some(_) => {}
+            },
+            State::CdataSection => match self.consume() {
+                Some(']') => self.state = State::CdataSectionBracket,
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::CdataSectionBracket => match self.consume() {
+                Some(']') => self.state = State::CdataSectionEnd,
+                _ => {
+                    self.emit_char(']');
+                    self.reconsume_in(State::CdataSection);
+                }
+            },
+            State::CdataSectionEnd => match self.consume() {
+                Some(']') => self.emit_char(']'),
+                Some('>') => self.state = State::Data,
+                _ => {
+                    self.text.push_str("]]");
+                    self.reconsume_in(State::CdataSection);
+                }
+            },
+            State::CharacterReference => {
+                self.temporary_buffer.clear();
+                self.temporary_buffer.push('&');
+                match self.consume() {
+                    Some(c) if c.is_ascii_alphanumeric() => {
+                        self.reconsume_in(State::NamedCharacterReference)
+                    }
+                    Some('#') => {
+                        self.temporary_buffer.push('#');
+                        self.state = State::NumericCharacterReference;
+                    }
+                    _ => {
+                        self.flush_code_points();
+                        let return_state = self.return_state;
+                        self.reconsume_in(return_state);
+                    }
+                }
+            }
+            State::NamedCharacterReference => self.consume_named_character_reference(),
+            State::NumericCharacterReference => {
+                self.character_reference_code = 0;
+                match self.consume() {
+                    Some(c @ ('x' | 'X')) => {
+                        self.temporary_buffer.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    }
+                    _ => self.reconsume_in(State::DecimalCharacterReferenceStart),
+                }
+            }
+            State::HexadecimalCharacterReferenceStart => match self.consume() {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    self.reconsume_in(State::HexadecimalCharacterReference)
+                }
+                _ => {
+                    // Absence of digits in numeric character reference.
+                    self.flush_code_points();
+                    let return_state = self.return_state;
+                    self.reconsume_in(return_state);
+                }
+            },
+            State::DecimalCharacterReferenceStart => match self.consume() {
+                Some(c) if c.is_ascii_digit() => {
+                    self.reconsume_in(State::DecimalCharacterReference)
+                }
+                _ => {
+                    self.flush_code_points();
+                    let return_state = self.return_state;
+                    self.reconsume_in(return_state);
+                }
+            },
+            State::HexadecimalCharacterReference => match self.consume() {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    self.push_reference_digit(16, c);
+                }
+                Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                _ => self.reconsume_in(State::NumericCharacterReferenceEnd),
+            },
+            State::DecimalCharacterReference => match self.consume() {
+                Some(c) if c.is_ascii_digit() => {
+                    self.push_reference_digit(10, c);
+                }
+                Some(';') => self.state = State::NumericCharacterReferenceEnd,
+                _ => self.reconsume_in(State::NumericCharacterReferenceEnd),
+            },
+            State::NumericCharacterReferenceEnd => {
+                let c = entities::numeric_reference_char(self.character_reference_code);
+                self.temporary_buffer.clear();
+                self.temporary_buffer.push(c);
+                self.flush_code_points();
+                self.state = self.return_state;
+            }
+        }
+    }
+
+    fn push_reference_digit(&mut self, radix: u32, digit: char) {
+        // Saturate rather than overflow; anything this large is out of range anyway.
+        self.character_reference_code = self
+            .character_reference_code
+            .saturating_mul(radix)
+            .saturating_add(digit.to_digit(radix).unwrap_or(0));
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#named-character-reference-state
+    fn consume_named_character_reference(&mut self) {
+        let rest = &self.input[self.pos..];
+        // Names are ASCII alphanumerics, optionally followed by `;`. Try the longest candidate
+        // first, so that e.g. `&notin;` isn't read as `&not` followed by `in;`.
+        let alphanumerics = rest.bytes().take_while(u8::is_ascii_alphanumeric).count();
+        let candidate_len = (alphanumerics + rest[alphanumerics..].starts_with(';') as usize)
+            .min(entities::LONGEST_NAME);
+        let found = (1..=candidate_len)
+            .rev()
+            .find_map(|len| entities::lookup(&rest[..len]).map(|value| (&rest[..len], value)));
+        match found {
+            Some((name, value)) => {
+                self.pos += name.len();
+                let next = self.input[self.pos..].chars().next();
+                // For historical reasons, a reference without a semicolon followed by `=` or an
+                // alphanumeric is left alone inside attribute values, e.g. `?a=1&copy=2`.
+                if self.in_attribute()
+                    && !name.ends_with(';')
+                    && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric())
+                {
+                    self.temporary_buffer.push_str(name);
+                } else {
+                    self.temporary_buffer.clear();
+                    self.temporary_buffer.push_str(value);
+                }
+                self.flush_code_points();
+                self.state = self.return_state;
+            }
+            None => {
+                // Not a known reference: keep the text as written.
+                let name: String = rest
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric())
+                    .collect();
+                self.pos += name.len();
+                self.temporary_buffer.push_str(&name);
+                self.flush_code_points();
+                self.state = self.return_state;
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::paint::Canvas;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Deflate "stored" blocks can hold at most this many bytes each.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+// Write a canvas to `path`, choosing the format from the file extension. Anything that isn't
+// `.ppm` is written as PNG.
+pub(crate) fn save(canvas: &Canvas, path: &Path) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("ppm") => write_ppm(canvas, &mut out)?,
+        _ => write_png(canvas, &mut out)?,
+    }
+    out.flush()
+}
+
+// Write a canvas as a binary (P6) PPM. PPM has no alpha channel, so it is dropped.
+pub(crate) fn write_ppm(canvas: &Canvas, out: &mut impl Write) -> std::io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", canvas.width, canvas.height)?;
+    let mut data = Vec::with_capacity(canvas.pixels.len() * 3);
+    for pixel in &canvas.pixels {
+        data.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    out.write_all(&data)
+}
+
+// Write a canvas as an 8-bit RGBA PNG.
+//
+// http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
+pub(crate) fn write_png(canvas: &Canvas, out: &mut impl Write) -> std::io::Result<()> {
+    out.write_all(&PNG_SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(canvas.width as u32).to_be_bytes());
+    header.extend_from_slice(&(canvas.height as u32).to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), default compression, filtering and no interlacing.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    // Each scanline starts with a filter type byte; we always use filter type 0 (None).
+    let mut raw = Vec::with_capacity(canvas.height * (canvas.width * 4 + 1));
+    for row in canvas.pixels.chunks(canvas.width.max(1)) {
+        raw.push(0);
+        for pixel in row {
+            raw.extend_from_slice(&[pixel.r, pixel.g, pixel.b, pixel.a]);
+        }
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(out, b"IEND", &[])
+}
+
+// Write a PNG chunk: length, type, data and a CRC over the type and data.
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    out.write_all(&crc.finish().to_be_bytes())
+}
+
+// Wrap `data` in a zlib stream made of uncompressed deflate blocks.
+//
+// https://www.rfc-editor.org/rfc/rfc1950 and https://www.rfc-editor.org/rfc/rfc1951#section-3.2.4
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+
+    // CMF: deflate with a 32K window. FLG: no dictionary, check bits make the header a multiple
+    // of 31.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        // An empty stream still needs one final (empty) block.
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let is_final = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(is_final as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest number of bytes that can be summed before `b` could overflow a u32.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+// CRC-32 as used by PNG (ISO 3309, reflected polynomial 0xedb88320).
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        Crc32 {
+            table,
+            crc: 0xffffffff,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
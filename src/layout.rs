@@ -0,0 +1,568 @@
+use crate::css::Unit::Px;
+use crate::css::Value::{Keyword, Length};
+use crate::style::{Display, StyledNode};
+use std::fmt::{Debug, Formatter};
+
+// We have no font metrics, so text is measured as if it were set in a fixed-width font.
+const DEFAULT_FONT_SIZE: f32 = 16.0;
+const CHAR_WIDTH_EM: f32 = 0.5;
+const LINE_HEIGHT_EM: f32 = 1.2;
+
+// CSS box model. All sizes are in px.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub(crate) struct Dimensions {
+    // Position of the content area relative to the document origin:
+    pub(crate) content: Rect,
+    // Surrounding edges:
+    pub(crate) padding: EdgeSizes,
+    pub(crate) border: EdgeSizes,
+    pub(crate) margin: EdgeSizes,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub(crate) struct Rect {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub(crate) struct EdgeSizes {
+    pub(crate) left: f32,
+    pub(crate) right: f32,
+    pub(crate) top: f32,
+    pub(crate) bottom: f32,
+}
+
+// A node in the layout tree.
+pub(crate) struct LayoutBox<'a> {
+    pub(crate) dimensions: Dimensions,
+    pub(crate) box_type: BoxType<'a>,
+    pub(crate) children: Vec<LayoutBox<'a>>,
+}
+
+pub(crate) enum BoxType<'a> {
+    BlockNode(&'a StyledNode<'a>),
+    InlineNode(&'a StyledNode<'a>),
+    AnonymousBlock,
+}
+
+impl<'a> LayoutBox<'a> {
+    fn new(box_type: BoxType<'a>) -> LayoutBox<'a> {
+        LayoutBox {
+            box_type,
+            dimensions: Default::default(),
+            children: Vec::new(),
+        }
+    }
+
+    pub(crate) fn get_style_node(&self) -> &'a StyledNode<'a> {
+        match self.box_type {
+            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
+            BoxType::AnonymousBlock => panic!("Anonymous block box has no style node"),
+        }
+    }
+
+    fn pretty_print(&self, prefix: String, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let label = match self.box_type {
+            BoxType::BlockNode(node) => format!("Block({})", node.summary()),
+            BoxType::InlineNode(node) => format!("Inline({})", node.summary()),
+            BoxType::AnonymousBlock => "AnonymousBlock".to_string(),
+        };
+        let rect = self.dimensions.content;
+        writeln!(
+            f,
+            "{}├──{} x: {}, y: {}, width: {}, height: {}",
+            prefix, label, rect.x, rect.y, rect.width, rect.height
+        )?;
+        for child in self.children.iter() {
+            child.pretty_print(format!("{}   ", prefix), f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for LayoutBox<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.pretty_print("".to_string(), f)
+    }
+}
+
+// Transform a style tree into a layout tree.
+pub(crate) fn layout_tree<'a>(
+    node: &'a StyledNode<'a>,
+    mut containing_block: Dimensions,
+) -> LayoutBox<'a> {
+    // A root element with `display: none` generates no boxes, so there is nothing to lay out.
+    if node.display() == Display::None {
+        return LayoutBox::new(BoxType::AnonymousBlock);
+    }
+
+    // The layout algorithm expects the container height to start at 0.
+    containing_block.content.height = 0.0;
+
+    let mut root_box = build_layout_tree(node);
+    root_box.layout(containing_block);
+    root_box
+}
+
+// Build the tree of LayoutBoxes, but don't perform any layout calculations yet.
+fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    match style_node.display() {
+        Display::Block => build_block(style_node),
+        Display::Inline => {
+            let mut pieces = build_inline(style_node);
+            if let [LayoutBox {
+                box_type: BoxType::InlineNode(_),
+                ..
+            }] = &pieces[..]
+            {
+                pieces.pop().unwrap()
+            } else {
+                // An inline root with blocks inside has no parent to hold its pieces, so it is
+                // laid out as a block instead.
+                build_block(style_node)
+            }
+        }
+        Display::None => unreachable!("no boxes are built for display: none"),
+    }
+}
+
+// Build the box for a block-level element, with runs of inline children wrapped in anonymous
+// blocks.
+fn build_block<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    let mut root = LayoutBox::new(BoxType::BlockNode(style_node));
+    for child in &style_node.children {
+        for piece in build_child(child) {
+            match piece.box_type {
+                BoxType::BlockNode(_) => root.children.push(piece),
+                _ => root.get_inline_container().children.push(piece),
+            }
+        }
+    }
+    // Whitespace between blocks is collapsed away and generates no boxes.
+    // https://www.w3.org/TR/CSS2/visuren.html#anonymous
+    root.children
+        .retain(|child| !child.is_collapsible_whitespace());
+    root
+}
+
+// Build the boxes for an inline element. Usually that's one box, but an inline element with
+// blocks inside is split around them: the runs of inline content on either side get boxes of
+// their own for the same element, with the blocks between them.
+// https://www.w3.org/TR/CSS2/visuren.html#anonymous-block-level
+fn build_inline<'a>(style_node: &'a StyledNode<'a>) -> Vec<LayoutBox<'a>> {
+    let mut pieces = vec![LayoutBox::new(BoxType::InlineNode(style_node))];
+    for child in &style_node.children {
+        for piece in build_child(child) {
+            match piece.box_type {
+                BoxType::BlockNode(_) => {
+                    pieces.push(piece);
+                    pieces.push(LayoutBox::new(BoxType::InlineNode(style_node)));
+                }
+                _ => pieces.last_mut().unwrap().children.push(piece),
+            }
+        }
+    }
+    if pieces.len() > 1 {
+        // Splitting leaves empty pieces, e.g. after a block at the end.
+        pieces.retain(|piece| {
+            matches!(piece.box_type, BoxType::BlockNode(_)) || !piece.children.is_empty()
+        });
+    }
+    pieces
+}
+
+fn build_child<'a>(style_node: &'a StyledNode<'a>) -> Vec<LayoutBox<'a>> {
+    match style_node.display() {
+        Display::Block => vec![build_block(style_node)],
+        Display::Inline => build_inline(style_node),
+        Display::None => Vec::new(), // Don't lay out nodes with `display: none;`
+    }
+}
+
+impl LayoutBox<'_> {
+    // Lay out a box and its descendants.
+    fn layout(&mut self, containing_block: Dimensions) {
+        match self.box_type {
+            BoxType::BlockNode(_) => self.layout_block(containing_block),
+            BoxType::InlineNode(_) => self.layout_inline_root(containing_block),
+            BoxType::AnonymousBlock => self.layout_anonymous_block(containing_block),
+        }
+    }
+
+    // Lay out a block-level element and its descendants.
+    fn layout_block(&mut self, containing_block: Dimensions) {
+        // Child width can depend on parent width, so we need to calculate this box's width before
+        // laying out its children.
+        self.calculate_block_width(containing_block);
+
+        // Determine where the box is located within its container.
+        self.calculate_block_position(containing_block);
+
+        // Recursively lay out the children of this box.
+        self.layout_block_children();
+
+        // Parent height can depend on child height, so `calculate_height` must be called after the
+        // children are laid out.
+        self.calculate_block_height();
+    }
+
+    // Calculate the width of a block-level non-replaced element in normal flow.
+    //
+    // http://www.w3.org/TR/CSS2/visudet.html#blockwidth
+    //
+    // Sets the horizontal margin/padding/border dimensions, and the `width`.
+    fn calculate_block_width(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node();
+
+        // `width` has initial value `auto`.
+        let auto = Keyword("auto".to_string());
+        let mut width = style.value("width").unwrap_or(auto.clone());
+
+        // margin, border, and padding have initial value 0.
+        let zero = Length(0.0, Px);
+
+        let mut margin_left = style.lookup("margin-left", "margin", &zero);
+        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+
+        let border_left = style.lookup("border-left-width", "border-width", &zero);
+        let border_right = style.lookup("border-right-width", "border-width", &zero);
+
+        let padding_left = style.lookup("padding-left", "padding", &zero);
+        let padding_right = style.lookup("padding-right", "padding", &zero);
+
+        let total: f32 = [
+            &margin_left,
+            &margin_right,
+            &border_left,
+            &border_right,
+            &padding_left,
+            &padding_right,
+            &width,
+        ]
+        .iter()
+        .map(|v| v.to_px())
+        .sum();
+
+        // If width is not auto and the total is wider than the container, treat auto margins as 0.
+        if width != auto && total > containing_block.content.width {
+            if margin_left == auto {
+                margin_left = Length(0.0, Px);
+            }
+            if margin_right == auto {
+                margin_right = Length(0.0, Px);
+            }
+        }
+
+        // Adjust used values so that the above sum equals `containing_block.width`.
+        // Each arm of the `match` should increase the total width by exactly `underflow`,
+        // and afterward all values should be absolute lengths in px.
+        let underflow = containing_block.content.width - total;
+
+        match (width == auto, margin_left == auto, margin_right == auto) {
+            // If the values are overconstrained, calculate margin_right.
+            (false, false, false) => {
+                margin_right = Length(margin_right.to_px() + underflow, Px);
+            }
+
+            // If exactly one size is auto, its used value follows from the equality.
+            (false, false, true) => {
+                margin_right = Length(underflow, Px);
+            }
+            (false, true, false) => {
+                margin_left = Length(underflow, Px);
+            }
+
+            // If width is set to auto, any other auto values become 0.
+            (true, _, _) => {
+                if margin_left == auto {
+                    margin_left = Length(0.0, Px);
+                }
+                if margin_right == auto {
+                    margin_right = Length(0.0, Px);
+                }
+
+                if underflow >= 0.0 {
+                    // Expand width to fill the underflow.
+                    width = Length(underflow, Px);
+                } else {
+                    // Width can't be negative. Adjust the right margin instead.
+                    width = Length(0.0, Px);
+                    margin_right = Length(margin_right.to_px() + underflow, Px);
+                }
+            }
+
+            // If margin-left and margin-right are both auto, their used values are equal.
+            (false, true, true) => {
+                margin_left = Length(underflow / 2.0, Px);
+                margin_right = Length(underflow / 2.0, Px);
+            }
+        }
+
+        let d = &mut self.dimensions;
+        d.content.width = width.to_px();
+
+        d.padding.left = padding_left.to_px();
+        d.padding.right = padding_right.to_px();
+
+        d.border.left = border_left.to_px();
+        d.border.right = border_right.to_px();
+
+        d.margin.left = margin_left.to_px();
+        d.margin.right = margin_right.to_px();
+    }
+
+    // Finish calculating the block's edge sizes, and position it within its containing block.
+    //
+    // http://www.w3.org/TR/CSS2/visudet.html#normal-block
+    //
+    // Sets the vertical margin/padding/border dimensions, and the `x`, `y` values.
+    fn calculate_block_position(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node();
+        let d = &mut self.dimensions;
+
+        // margin, border, and padding have initial value 0.
+        let zero = Length(0.0, Px);
+
+        // If margin-top or margin-bottom is `auto`, the used value is zero.
+        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
+        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+
+        d.border.top = style
+            .lookup("border-top-width", "border-width", &zero)
+            .to_px();
+        d.border.bottom = style
+            .lookup("border-bottom-width", "border-width", &zero)
+            .to_px();
+
+        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
+        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+
+        d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+
+        // Position the box below all the previous boxes in the container.
+        d.content.y = containing_block.content.height
+            + containing_block.content.y
+            + d.margin.top
+            + d.border.top
+            + d.padding.top;
+    }
+
+    // Lay out the block's children within its content area.
+    //
+    // Sets `self.dimensions.height` to the total content height.
+    fn layout_block_children(&mut self) {
+        let d = &mut self.dimensions;
+        for child in &mut self.children {
+            child.layout(*d);
+            // Increment the height so each child is laid out below the previous one.
+            d.content.height += child.dimensions.margin_box().height;
+        }
+    }
+
+    // Height of a block-level non-replaced element in normal flow with overflow visible.
+    fn calculate_block_height(&mut self) {
+        // If the height is set to an explicit length, use that exact length.
+        // Otherwise, just keep the value set by `layout_block_children`.
+        if let Some(Length(h, Px)) = self.get_style_node().value("height") {
+            self.dimensions.content.height = h;
+        }
+    }
+
+    // Lay out an anonymous block, which wraps a run of inline boxes into lines.
+    fn layout_anonymous_block(&mut self, containing_block: Dimensions) {
+        let d = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = containing_block.content.width;
+        self.layout_lines();
+    }
+
+    // Lay out an inline box that has no block container, e.g. an inline root element.
+    fn layout_inline_root(&mut self, containing_block: Dimensions) {
+        self.layout_inline_box(&mut true);
+        self.translate(
+            containing_block.content.x,
+            containing_block.content.y + containing_block.content.height,
+        );
+    }
+
+    // Place inline children left to right, starting a new line whenever a box would overflow the
+    // content width. Boxes are never split across lines, and a space at the end of a line is kept.
+    //
+    // Sets `self.dimensions.height` to the total height of all lines.
+    fn layout_lines(&mut self) {
+        let d = &mut self.dimensions;
+        let (mut x, mut y, mut line_height) = (0.0, 0.0, 0.0_f32);
+        let mut after_space = true;
+        for child in &mut self.children {
+            let start_after_space = after_space;
+            child.layout_inline_box(&mut after_space);
+            let mut margin_box = child.dimensions.margin_box();
+            if x > 0.0 && x + margin_box.width > d.content.width {
+                y += line_height;
+                x = 0.0;
+                line_height = 0.0;
+                // Measure the box again, since a space at the start of a line is dropped.
+                if !start_after_space {
+                    after_space = true;
+                    child.layout_inline_box(&mut after_space);
+                    margin_box = child.dimensions.margin_box();
+                }
+            }
+            child.translate(d.content.x + x, d.content.y + y);
+            x += margin_box.width;
+            line_height = line_height.max(margin_box.height);
+        }
+        d.content.height = y + line_height;
+    }
+
+    // Size an inline box and its descendants, with its margin box placed at the origin.
+    //
+    // `after_space` says whether the text so far ends in a space (or the line is empty), and is
+    // updated for the text in this box.
+    fn layout_inline_box(&mut self, after_space: &mut bool) {
+        let style = self.get_style_node();
+        let zero = Length(0.0, Px);
+        let d = &mut self.dimensions;
+
+        d.margin.left = style.lookup("margin-left", "margin", &zero).to_px();
+        d.margin.right = style.lookup("margin-right", "margin", &zero).to_px();
+        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
+        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+
+        d.border.left = style
+            .lookup("border-left-width", "border-width", &zero)
+            .to_px();
+        d.border.right = style
+            .lookup("border-right-width", "border-width", &zero)
+            .to_px();
+        d.border.top = style
+            .lookup("border-top-width", "border-width", &zero)
+            .to_px();
+        d.border.bottom = style
+            .lookup("border-bottom-width", "border-width", &zero)
+            .to_px();
+
+        d.padding.left = style.lookup("padding-left", "padding", &zero).to_px();
+        d.padding.right = style.lookup("padding-right", "padding", &zero).to_px();
+        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
+        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+
+        d.content.x = d.margin.left + d.border.left + d.padding.left;
+        d.content.y = d.margin.top + d.border.top + d.padding.top;
+
+        let font_size = match style.value("font-size") {
+            Some(Length(size, Px)) => size,
+            _ => DEFAULT_FONT_SIZE,
+        };
+        if let Some(text) = style.text() {
+            let chars = collapsed_len(text, after_space);
+            d.content.width = chars as f32 * font_size * CHAR_WIDTH_EM;
+            // Text that collapses away entirely doesn't make the line any taller.
+            d.content.height = if chars > 0 {
+                font_size * LINE_HEIGHT_EM
+            } else {
+                0.0
+            };
+            return;
+        }
+
+        let (mut width, mut height) = (0.0, 0.0_f32);
+        for child in &mut self.children {
+            child.layout_inline_box(after_space);
+            child.translate(d.content.x + width, d.content.y);
+            let margin_box = child.dimensions.margin_box();
+            width += margin_box.width;
+            height = height.max(margin_box.height);
+        }
+        d.content.width = width;
+        d.content.height = height;
+    }
+
+    // Move this box and all of its descendants by the given offset.
+    fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content.x += dx;
+        self.dimensions.content.y += dy;
+        for child in &mut self.children {
+            child.translate(dx, dy);
+        }
+    }
+
+    // Whether this is an anonymous block holding nothing but whitespace.
+    fn is_collapsible_whitespace(&self) -> bool {
+        matches!(self.box_type, BoxType::AnonymousBlock)
+            && self.children.iter().all(|child| match child.box_type {
+                BoxType::InlineNode(node) => node
+                    .text()
+                    .is_some_and(|text| text.chars().all(|c| c.is_ascii_whitespace())),
+                _ => false,
+            })
+    }
+
+    // Where a new inline child should go.
+    fn get_inline_container(&mut self) -> &mut Self {
+        match self.box_type {
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
+            BoxType::BlockNode(_) => {
+                // If we've just generated an anonymous block box, keep using it.
+                // Otherwise, create a new one.
+                match self.children.last() {
+                    Some(&LayoutBox {
+                        box_type: BoxType::AnonymousBlock,
+                        ..
+                    }) => {}
+                    _ => self.children.push(LayoutBox::new(BoxType::AnonymousBlock)),
+                }
+                self.children.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+// The length of `text` once each run of whitespace is collapsed to a single space, with a space
+// dropped altogether if it follows `after_space`. Only `white-space: normal` is supported.
+// https://www.w3.org/TR/css-text-3/#white-space-phase-1
+fn collapsed_len(text: &str, after_space: &mut bool) -> usize {
+    let mut len = 0;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !*after_space {
+                len += 1;
+            }
+            *after_space = true;
+        } else {
+            len += 1;
+            *after_space = false;
+        }
+    }
+    len
+}
+
+impl Rect {
+    pub(crate) fn expanded_by(self, edge: EdgeSizes) -> Rect {
+        Rect {
+            x: self.x - edge.left,
+            y: self.y - edge.top,
+            width: self.width + edge.left + edge.right,
+            height: self.height + edge.top + edge.bottom,
+        }
+    }
+}
+
+impl Dimensions {
+    // The area covered by the content area plus its padding.
+    pub(crate) fn padding_box(self) -> Rect {
+        self.content.expanded_by(self.padding)
+    }
+    // The area covered by the content area plus padding and borders.
+    pub(crate) fn border_box(self) -> Rect {
+        self.padding_box().expanded_by(self.border)
+    }
+    // The area covered by the content area plus padding, borders, and margin.
+    pub(crate) fn margin_box(self) -> Rect {
+        self.border_box().expanded_by(self.margin)
+    }
+}
@@ -1,27 +1,175 @@
-use crate::html::elem;
-use crate::html::text;
-use crate::html::AttrMap;
+use crate::cli::{Args, OutputMode, USAGE};
+use crate::css::{Origin, Stylesheet};
+use crate::html::Node;
+use crate::image::save;
+use crate::layout::{layout_tree, Dimensions};
+use crate::paint::paint;
 use crate::parser::Parser;
-use std::collections::HashMap;
+use crate::style::{style_tree, USER_AGENT_STYLESHEET};
+use crate::stylesheets::{style_attribute_errors, style_sources, StyleSource};
 use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::{env, io, process};
 
+mod ancestor_filter;
+mod cli;
 mod css;
+mod css_tokenizer;
+mod entities;
 mod html;
+mod html_tokenizer;
+mod image;
+mod layout;
+mod paint;
 mod parser;
+mod properties;
+mod rule_index;
+mod style;
+mod stylesheets;
+mod tree_builder;
 
-fn main() -> std::io::Result<()> {
-    let input = fs::read_to_string("input/input1.html")?;
-    let mut parser = Parser::new(input);
-    let root = parser.parse_html();
-    println!("{:?}", root);
-    Ok(())
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let document = Parser::new(read_input(&args.html)?).parse_document();
+    let root = document.document_element();
+    let stylesheets = load_stylesheets(args, root)?;
+    for (tag_name, error) in style_attribute_errors(root) {
+        eprintln!(
+            "warning: {}: style attribute of <{}>: {}",
+            args.html.display(),
+            tag_name,
+            error
+        );
+    }
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = args.width;
+    viewport.content.height = args.height;
+
+    let text = match args.mode {
+        OutputMode::Dom => format!("{:?}", document),
+        // The user-agent stylesheet is left out; it is the same for every document.
+        OutputMode::Stylesheet => format!("{:#?}\n", &stylesheets[1..]),
+        OutputMode::Style => format!("{:?}", style_tree(root, &stylesheets)),
+        OutputMode::Layout => {
+            let styled_root = style_tree(root, &stylesheets);
+            format!("{:?}", layout_tree(&styled_root, viewport))
+        }
+        OutputMode::Image => {
+            let styled_root = style_tree(root, &stylesheets);
+            let layout_root = layout_tree(&styled_root, viewport);
+            let canvas = paint(&layout_root, viewport.content);
+            // `Args::parse` always picks an output path for images.
+            return save(&canvas, args.output.as_deref().unwrap());
+        }
+    };
+    match &args.output {
+        Some(path) => fs::write(path, text),
+        None => io::stdout().write_all(text.as_bytes()),
+    }
+}
+
+// Collect the stylesheets in cascade order: the built-in defaults, the user's sheets from the
+// command line, then the document's own `<style>` and `<link>` sheets in document order.
+fn load_stylesheets(args: &Args, root: &Node) -> io::Result<Vec<Stylesheet>> {
+    let mut user_agent = Parser::new(USER_AGENT_STYLESHEET.to_string()).parse_css();
+    user_agent.origin = Origin::UserAgent;
+    let mut stylesheets = vec![user_agent];
+    for path in &args.css {
+        let mut stylesheet = load_css(path)?;
+        stylesheet.origin = Origin::User;
+        stylesheets.push(stylesheet);
+    }
+    let base = args.html.parent().unwrap_or(Path::new(""));
+    let mut style_elements = 0;
+    for source in style_sources(root, base) {
+        match source {
+            // Positions in a `<style>` element's errors are relative to its text, so say which
+            // element it is.
+            StyleSource::Inline(css) => {
+                style_elements += 1;
+                let source = format!("{}: <style> #{}", args.html.display(), style_elements);
+                stylesheets.push(parse_css(css, &source));
+            }
+            // A sheet the page links to but that can't be loaded is skipped, as it is in browsers.
+            StyleSource::Link(path) => match load_css(&path) {
+                Ok(stylesheet) => stylesheets.push(stylesheet),
+                Err(e) => eprintln!("warning: {}", e),
+            },
+        }
+    }
+    Ok(stylesheets)
+}
+
+fn load_css(path: &Path) -> io::Result<Stylesheet> {
+    Ok(parse_css(read_input(path)?, &path.display().to_string()))
+}
+
+// Parse a stylesheet. Errors in it don't stop the render; they are reported as warnings naming
+// `source`, and the declarations and rules they were in are left out.
+fn parse_css(css: String, source: &str) -> Stylesheet {
+    let mut parser = Parser::new(css);
+    let stylesheet = parser.parse_css();
+    for diagnostic in &parser.diagnostics {
+        eprintln!("warning: {}: {}", source, diagnostic);
+    }
+    stylesheet
+}
+
+// Read a file, or standard input if the path is `-`.
+fn read_input(path: &Path) -> io::Result<String> {
+    if path == Path::new("-") {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        return Ok(input);
+    }
+    fs::read_to_string(path).map_err(|e| with_path(path, e))
+}
+
+// Prefix an error message with the file it relates to.
+fn with_path(path: &Path, error: impl Into<io::Error>) -> io::Error {
+    let error = error.into();
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::css::{Color, Declaration, Rule, Selector, SimpleSelector, Stylesheet, Unit, Value};
-    use crate::html::Node;
+    use crate::ancestor_filter::{selector_hashes, AncestorFilter};
+    use crate::cli::{Args, OutputMode};
+    use crate::css::{
+        AttributeOperator, AttributeSelector, Color, Combinator, ContentItem, Declaration, Nth,
+        PseudoClass, Rule, Selector, Separator, SimpleSelector, Stylesheet, Unit, Value,
+    };
+    use crate::css_tokenizer::{tokenize, Number, Token as CssToken};
+    use crate::html::{comment, elem, text, AttrMap, DoctypeData, Node, NodeType, QuirksMode};
+    use crate::html_tokenizer::{Doctype, Tag, Token, Tokenizer};
+    use crate::image::{write_png, write_ppm};
+    use crate::layout::{BoxType, LayoutBox, Rect};
+    use crate::paint::Canvas;
+    use crate::parser::ParseError;
+    use crate::rule_index::RuleIndex;
+    use crate::style::{style_tree_unindexed, Display, PropertyMap, StyledNode};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
 
     fn manually_build_test_1() -> Node {
         let mut root = elem(
@@ -92,9 +240,38 @@ mod tests {
         root.children[0].children[1].children[0]
             .children
             .push(exclamation);
+        let head = elem(
+            "head".to_string(),
+            AttrMap {
+                attrs: HashMap::new(),
+            },
+            Vec::new(),
+        );
+        // Comments are kept in the DOM, between the elements around them.
+        root.children[0]
+            .children
+            .insert(1, comment("Example comment".to_string()));
+        // The parser implies a <head> before the <body>.
+        root.children.insert(0, head);
+        // Whitespace between the tags is kept as text.
+        insert_whitespace(
+            &mut root.children[1],
+            &[(0, "\n"), (2, "\n"), (4, "\n"), (6, "\n\n\n")],
+        );
+        insert_whitespace(
+            &mut root.children[1].children[5],
+            &[(0, "\n    "), (2, "\n")],
+        );
         root
     }
 
+    // Insert whitespace-only text nodes into `node` at the given positions, in order.
+    fn insert_whitespace(node: &mut Node, whitespace: &[(usize, &str)]) {
+        for &(i, data) in whitespace {
+            node.children.insert(i, text(data.to_string()));
+        }
+    }
+
     fn manually_build_test_2() -> Node {
         let mut root = elem(
             "html".to_string(),
@@ -134,7 +311,8 @@ mod tests {
         );
         root.children[0].children.push(style);
         let style_text = text(
-            r#"body {
+            r#"
+        body {
             font-family: Arial, sans-serif;
             text-align: center;
             margin: 50px;
@@ -205,33 +383,47 @@ mod tests {
         );
         root.children[1].children.push(script);
         let script_text = text(
-            r#"function showMessage() {
+            r#"
+    function showMessage() {
         alert("Hello, world!");
     }
 "#
             .to_string(),
         );
         root.children[1].children[3].children.push(script_text);
+        insert_whitespace(
+            &mut root.children[0],
+            &[(0, "\n    "), (2, "\n    "), (4, "\n")],
+        );
+        insert_whitespace(
+            &mut root.children[1],
+            &[
+                (0, "\n\n"),
+                (2, "\n"),
+                (4, "\n\n"),
+                (6, "\n\n"),
+                (8, "\n\n\n\n"),
+            ],
+        );
+        insert_whitespace(&mut root, &[(1, "\n")]);
         root
     }
 
     #[test]
     fn test_parse_html_1() -> std::io::Result<()> {
         let input = fs::read_to_string("input/input1.html")?;
-        let mut parser = Parser::new(input);
-        let parsed_root = parser.parse_html();
+        let document = Parser::new(input).parse_document();
         let manual_root = manually_build_test_1();
-        assert_eq!(parsed_root, manual_root);
+        assert_eq!(document.document_element(), &manual_root);
         Ok(())
     }
 
     #[test]
     fn test_parse_html_2() -> std::io::Result<()> {
         let input = fs::read_to_string("input/input2.html")?;
-        let mut parser = Parser::new(input);
-        let parsed_root = parser.parse_html();
+        let document = Parser::new(input).parse_document();
         let manual_root = manually_build_test_2();
-        assert_eq!(parsed_root, manual_root);
+        assert_eq!(document.document_element(), &manual_root);
         Ok(())
     }
 
@@ -244,28 +436,39 @@ mod tests {
                             tag_name: Some("h1".to_string()),
                             id: None,
                             class: vec![],
+                            attributes: vec![],
+                            pseudo_classes: vec![],
+                            pseudo_element: None,
                         }),
                         Selector::Simple(SimpleSelector {
                             tag_name: Some("h2".to_string()),
                             id: None,
                             class: vec![],
+                            attributes: vec![],
+                            pseudo_classes: vec![],
+                            pseudo_element: None,
                         }),
                         Selector::Simple(SimpleSelector {
                             tag_name: Some("h3".to_string()),
                             id: None,
                             class: vec![],
+                            attributes: vec![],
+                            pseudo_classes: vec![],
+                            pseudo_element: None,
                         }),
                     ],
                     declarations: vec![
                         Declaration {
                             name: "margin".to_string(),
                             value: Value::Keyword("auto".to_string()),
+                            important: false,
                         },
                         Declaration {
                             name: "color".to_string(),
                             value: Value::ColorValue(
                                 Color::try_from("#cc0000".to_string()).unwrap(),
                             ),
+                            important: false,
                         },
                     ],
                 },
@@ -274,15 +477,20 @@ mod tests {
                         tag_name: Some("div".to_string()),
                         id: None,
                         class: vec!["note".to_string()],
+                        attributes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     })],
                     declarations: vec![
                         Declaration {
                             name: "margin-bottom".to_string(),
                             value: Value::Length(20f32, Unit::Px),
+                            important: false,
                         },
                         Declaration {
                             name: "padding".to_string(),
                             value: Value::Length(10f32, Unit::Px),
+                            important: false,
                         },
                     ],
                 },
@@ -291,13 +499,18 @@ mod tests {
                         tag_name: None,
                         id: Some("answer".to_string()),
                         class: Vec::new(),
+                        attributes: Vec::new(),
+                        pseudo_classes: Vec::new(),
+                        pseudo_element: None,
                     })],
                     declarations: vec![Declaration {
                         name: "display".to_string(),
                         value: Value::Keyword("none".to_string()),
+                        important: false,
                     }],
                 },
             ],
+            origin: Origin::Author,
         }
     }
 
@@ -310,4 +523,1749 @@ mod tests {
         assert_eq!(parsed_stylesheet, manual_stylesheet);
         Ok(())
     }
+
+    #[test]
+    fn test_style_tree_1() -> std::io::Result<()> {
+        let input = fs::read_to_string("input/input1.html")?;
+        let document = Parser::new(input).parse_document();
+        let root = document.document_element();
+        let stylesheet = Parser::new(
+            "div { padding: 5px; } .test { padding: 10px; } #main { display: block; } p { color: #cc0000; }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(root, &[stylesheet]);
+
+        // The whitespace around the comment is kept as two text nodes.
+        let div = &styled_root.children[1].children[4];
+        assert_eq!(div.specified_values.len(), 2);
+        assert_eq!(
+            div.specified_values.get("padding"),
+            Some(&Value::Length(10f32, Unit::Px))
+        );
+        assert_eq!(
+            div.specified_values.get("display"),
+            Some(&Value::Keyword("block".to_string()))
+        );
+        let p = &div.children[1];
+        assert_eq!(
+            p.specified_values.get("color"),
+            Some(&Value::ColorValue(
+                Color::try_from("#cc0000".to_string()).unwrap()
+            ))
+        );
+        // Text nodes take inherited properties from their parent, and nothing else.
+        let text = &p.children[0];
+        assert_eq!(text.specified_values.len(), 1);
+        assert_eq!(text.value("color"), p.value("color"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_style_attribute() {
+        let document = Parser::new(
+            "<div id=a style='color: #ff0000; margin:4px'>x</div><p id=b style='color: #zz'>y</p>"
+                .to_string(),
+        )
+        .parse_document();
+        let stylesheet =
+            Parser::new("#a, #b { color: #0000ff; margin: 1px; padding: 2px; }".to_string())
+                .parse_css();
+        let styled_root = style_tree(document.document_element(), &[stylesheet]);
+        let body = &styled_root.children[1];
+
+        // The `style` attribute wins over the more specific id selector.
+        let div = &body.children[0];
+        assert_eq!(
+            div.value("color"),
+            Some(Value::ColorValue(
+                Color::try_from("#ff0000".to_string()).unwrap()
+            ))
+        );
+        assert_eq!(div.value("margin"), Some(Value::Length(4.0, Unit::Px)));
+        assert_eq!(div.value("padding"), Some(Value::Length(2.0, Unit::Px)));
+
+        // An invalid declaration in a `style` attribute is ignored.
+        let p = &body.children[1];
+        assert_eq!(
+            p.value("color"),
+            Some(Value::ColorValue(
+                Color::try_from("#0000ff".to_string()).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_user_agent_stylesheet() -> std::io::Result<()> {
+        let input = fs::read_to_string("input/input1.html")?;
+        let document = Parser::new(input).parse_document();
+        let mut user_agent = Parser::new(USER_AGENT_STYLESHEET.to_string()).parse_css();
+        user_agent.origin = Origin::UserAgent;
+        let author =
+            Parser::new("h1 { margin: 10px; } * { display: inline; }".to_string()).parse_css();
+        let styled_root = style_tree(document.document_element(), &[user_agent, author]);
+
+        let body = &styled_root.children[1];
+        assert_eq!(body.value("margin"), Some(Value::Length(8.0, Unit::Px)));
+
+        // Author rules win over the defaults whatever their specificity.
+        let head = &styled_root.children[0];
+        assert_eq!(head.display(), style::Display::Inline);
+
+        // The author's `margin` shorthand replaces the default `margin-top`, and properties the
+        // author doesn't set keep their defaults.
+        let h1 = &body.children[1];
+        assert_eq!(h1.value("margin-top"), None);
+        assert_eq!(h1.value("margin"), Some(Value::Length(10.0, Unit::Px)));
+        assert_eq!(h1.value("font-size"), Some(Value::Length(32.0, Unit::Px)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cascade() {
+        let document = Parser::new(
+            "<div id=x class=c style='color: #000001; padding: 1px !important'></div>".to_string(),
+        )
+        .parse_document();
+        let sheet = |css: &str, origin| -> Stylesheet {
+            let mut stylesheet = Parser::new(css.to_string()).parse_css();
+            stylesheet.origin = origin;
+            stylesheet
+        };
+        let stylesheets = [
+            sheet(
+                "div { display: block !important; margin: 1px; }",
+                Origin::UserAgent,
+            ),
+            sheet("div { color: #000002 ! IMPORTANT; margin: 2px; }", Origin::User),
+            sheet(
+                "#x { color: #000003 !important; padding: 3px !important; display: inline; margin: 3px; } \
+                 .c { border-width: 1px; }",
+                Origin::Author,
+            ),
+            sheet(".c { border-width: 2px; } div { margin: 4px; }", Origin::Author),
+        ];
+        let styled_root = style_tree(document.document_element(), &stylesheets);
+        let div = &styled_root.children[1].children[0];
+        let color = |hex: &str| Some(Value::ColorValue(Color::try_from(hex.to_string()).unwrap()));
+
+        // Important user-agent declarations beat everything else.
+        assert_eq!(
+            div.value("display"),
+            Some(Value::Keyword("block".to_string()))
+        );
+        // Important user declarations beat important author ones.
+        assert_eq!(div.value("color"), color("#000002"));
+        // Within the author origin, the style attribute beats any selector.
+        assert_eq!(div.value("padding"), Some(Value::Length(1.0, Unit::Px)));
+        // Then specificity decides, whatever the order of the sheets...
+        assert_eq!(div.value("margin"), Some(Value::Length(3.0, Unit::Px)));
+        // ...and the later sheet wins a tie.
+        assert_eq!(
+            div.value("border-width"),
+            Some(Value::Length(2.0, Unit::Px))
+        );
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let document =
+            Parser::new("<div id=a><p id=b><span id=c>t</span></p><p id=d>u</p></div>".to_string())
+                .parse_document();
+        let stylesheet = Parser::new(
+            "#a { color: #ff0000; margin: 5px; font-size: 20px; width: 100px; } \
+             #b { margin: inherit; color: initial; width: unset; } \
+             #c { font-size: unset; display: INHERIT; } \
+             #d { font-size: initial; padding: inherit; }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(document.document_element(), &[stylesheet]);
+        let a = &styled_root.children[1].children[0];
+        let (b, d) = (&a.children[0], &a.children[1]);
+        let c = &b.children[0];
+        let px = |px| Some(Value::Length(px, Unit::Px));
+        let black = Some(Value::ColorValue(
+            Color::try_from("#000000".to_string()).unwrap(),
+        ));
+
+        // `font-size` inherits, `margin` only when asked to.
+        assert_eq!(b.value("font-size"), px(20.0));
+        assert_eq!(b.value("margin"), px(5.0));
+        assert_eq!(d.value("margin"), None);
+        // `initial` and `unset` on a property that doesn't inherit give its initial value.
+        assert_eq!(b.value("color"), black);
+        assert_eq!(b.value("width"), Some(Value::Keyword("auto".to_string())));
+        assert_eq!(d.value("font-size"), px(16.0));
+        // Inheriting a value the parent doesn't have gives the initial value.
+        assert_eq!(d.value("padding"), px(0.0));
+        assert_eq!(
+            c.value("display"),
+            Some(Value::Keyword("inline".to_string()))
+        );
+        // `unset` on an inherited property inherits.
+        assert_eq!(c.value("font-size"), px(20.0));
+
+        // Text takes its parent's inherited properties.
+        let text = &c.children[0];
+        assert_eq!(text.value("color"), black);
+        assert_eq!(text.value("font-size"), px(20.0));
+        assert_eq!(text.value("display"), None);
+    }
+
+    #[test]
+    fn test_inherit_shorthands() {
+        let document = Parser::new(
+            "<div style='margin: 10px 20px; padding: 3px; border-width: 1px 2px 3px'>\
+             <p style='margin: inherit; padding: inherit; padding-left: 1px; border-width: initial'>\
+             <span style='margin-top: inherit; padding-right: inherit'>x</span></p></div>"
+                .to_string(),
+        )
+        .parse_document();
+        let styled_root = style_tree(document.document_element(), &[]);
+        let p = &styled_root.children[1].children[0].children[0];
+        let span = &p.children[0];
+        let px = |px| Some(Value::Length(px, Unit::Px));
+
+        // Defaulting a shorthand defaults each of its longhands.
+        let margins = ["margin-top", "margin-right", "margin-bottom", "margin-left"];
+        assert_eq!(
+            margins.map(|name| p.value(name)),
+            [10.0, 20.0, 10.0, 20.0].map(px)
+        );
+        assert_eq!(p.value("border-top-width"), px(0.0));
+        assert_eq!(p.value("border-right-width"), px(0.0));
+        // A single value is only stored on the parent's shorthand, but still inherited by each
+        // longhand. Longhands declared afterwards win.
+        assert_eq!(p.value("padding-top"), px(3.0));
+        assert_eq!(p.value("padding-left"), px(1.0));
+
+        assert_eq!(span.value("margin-top"), px(10.0));
+        assert_eq!(span.value("padding-right"), px(3.0));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let stylesheet =
+            Parser::new("ul>li  +li ~ p.x a { color: #000000; }".to_string()).parse_css();
+        let simple = |tag_name: &str, class: &[&str]| SimpleSelector {
+            tag_name: Some(tag_name.to_string()),
+            id: None,
+            class: class.iter().map(|c| c.to_string()).collect(),
+            attributes: vec![],
+            pseudo_classes: vec![],
+            pseudo_element: None,
+        };
+        let ul_li = Selector::Complex(
+            Box::new(Selector::Simple(simple("ul", &[]))),
+            Combinator::Child,
+            simple("li", &[]),
+        );
+        let expected = Selector::Complex(
+            Box::new(Selector::Complex(
+                Box::new(Selector::Complex(
+                    Box::new(ul_li),
+                    Combinator::NextSibling,
+                    simple("li", &[]),
+                )),
+                Combinator::SubsequentSibling,
+                simple("p", &["x"]),
+            )),
+            Combinator::Descendant,
+            simple("a", &[]),
+        );
+        assert_eq!(stylesheet.rules[0].selectors, vec![expected]);
+        assert_eq!(stylesheet.rules[0].selectors[0].specificity(), (0, 1, 5));
+
+        let document = Parser::new(
+            "<div id=d><p id=p1><span><em id=e>x</em></span></p><p id=p2></p>text<p id=p3></p></div>"
+                .to_string(),
+        )
+        .parse_document();
+        let stylesheet = Parser::new(
+            "div em { margin: 1px; } p > em { margin: 2px; } #p1 + p { margin: 3px; } \
+             #p1 ~ p { padding: 4px; } #p2 + p { border-width: 5px; } p ~ #p1 { padding: 6px; }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(document.document_element(), &[stylesheet]);
+        let div = &styled_root.children[1].children[0];
+        let (p1, p2, p3) = (&div.children[0], &div.children[1], &div.children[3]);
+        let px = |px| Some(Value::Length(px, Unit::Px));
+
+        // A descendant matches at any depth, a child only directly.
+        let em = &p1.children[0].children[0];
+        assert_eq!(em.value("margin"), px(1.0));
+        // Sibling combinators skip text between elements.
+        assert_eq!(p2.value("margin"), px(3.0));
+        assert_eq!(p3.value("margin"), None);
+        assert_eq!(p2.value("padding"), px(4.0));
+        assert_eq!(p3.value("padding"), px(4.0));
+        assert_eq!(p3.value("border-width"), px(5.0));
+        // Siblings only look backwards.
+        assert_eq!(p1.value("padding"), None);
+
+        // Type selectors match whatever the case.
+        let stylesheet = Parser::new("DIV P#p2 { margin: 7px; }".to_string()).parse_css();
+        let styled_root = style_tree(document.document_element(), &[stylesheet]);
+        let div = &styled_root.children[1].children[0];
+        assert_eq!(div.children[1].value("margin"), px(7.0));
+    }
+
+    #[test]
+    fn test_attribute_selectors() {
+        let stylesheet =
+            Parser::new("a[ HREF $= '.pdf' i] { color: #000000; }".to_string()).parse_css();
+        let expected = AttributeSelector {
+            name: "href".to_string(),
+            matcher: Some((AttributeOperator::Suffix, ".pdf".to_string())),
+            case_insensitive: true,
+        };
+        assert_eq!(
+            stylesheet.rules[0].selectors,
+            vec![Selector::Simple(SimpleSelector {
+                tag_name: Some("a".to_string()),
+                id: None,
+                class: vec![],
+                attributes: vec![expected],
+                pseudo_classes: vec![],
+                pseudo_element: None,
+            })]
+        );
+        assert_eq!(stylesheet.rules[0].selectors[0].specificity(), (0, 1, 1));
+
+        let document = Parser::new(
+            "<p id=a lang=en-US data-state=open title='Foo bar'></p>\
+             <p id=b lang=EN class='x y' href=http://a/b.PDF data-state></p>"
+                .to_string(),
+        )
+        .parse_document();
+        let body = &document.document_element().children[1];
+        let matching = |selector: &str| -> Vec<String> {
+            let css = format!("{} {{ color: #000000; }}", selector);
+            let stylesheet = Parser::new(css).parse_css();
+            let styled = style_tree(body, &[stylesheet]);
+            let matched = styled
+                .children
+                .iter()
+                .filter(|p| p.value("color").is_some());
+            matched
+                .filter_map(|p| match &p.node.node_type {
+                    NodeType::Element(data) => data.id().cloned(),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(matching("[data-state]"), ["a", "b"]);
+        assert_eq!(matching("[data-state=open]"), ["a"]);
+        assert_eq!(matching("[lang|=en]"), ["a"]);
+        assert_eq!(matching("[lang|=en i]"), ["a", "b"]);
+        assert_eq!(matching("[class~=y]"), ["b"]);
+        assert_eq!(matching("[class~='x y']"), Vec::<String>::new());
+        assert_eq!(matching("[href^=http]"), ["b"]);
+        assert_eq!(matching("[href$=.pdf]"), Vec::<String>::new());
+        assert_eq!(matching("[href$=.pdf i]"), ["b"]);
+        assert_eq!(matching("[title*=\"o b\"]"), ["a"]);
+        assert_eq!(matching("[title*='']"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_pseudo_classes() {
+        let stylesheet = Parser::new(
+            "li:nth-child(-n + 3 of .x):not(#a, p) { color: #000000; } \
+             :where(ul li):first-child { color: #000000; } \
+             :is(ul li, #a):has(> b) { color: #000000; }"
+                .to_string(),
+        )
+        .parse_css();
+        let specificities: Vec<_> = stylesheet
+            .rules
+            .iter()
+            .map(|rule| rule.selectors[0].specificity())
+            .collect();
+        assert_eq!(specificities, [(1, 2, 1), (0, 1, 0), (1, 0, 1)]);
+        match &stylesheet.rules[0].selectors[0] {
+            Selector::Simple(simple) => assert_eq!(
+                simple.pseudo_classes[0],
+                PseudoClass::NthChild(
+                    Nth { a: -1, b: 3 },
+                    Some(vec![Selector::Simple(SimpleSelector {
+                        tag_name: None,
+                        id: None,
+                        class: vec!["x".to_string()],
+                        attributes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
+                    })])
+                )
+            ),
+            selector => panic!("unexpected selector {:?}", selector),
+        }
+
+        let document = Parser::new(
+            "<ul><li id=a class=x></li><li id=b><b></b></li><p id=c></p><li id=d class=x></li>\
+             <li id=e class=x><!-- --></li><li id=f class=x>text</li></ul>"
+                .to_string(),
+        )
+        .parse_document();
+        let root = document.document_element();
+        let matching = |selector: &str| -> Vec<String> {
+            let css = format!("{} {{ margin: 1px; }}", selector);
+            let stylesheet = Parser::new(css).parse_css();
+            let styled = style_tree(root, &[stylesheet]);
+            let ul = &styled.children[1].children[0];
+            let matched = ul.children.iter().filter(|li| li.value("margin").is_some());
+            matched
+                .filter_map(|li| match &li.node.node_type {
+                    NodeType::Element(data) => data.id().cloned(),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(matching(":first-child"), ["a"]);
+        assert_eq!(matching(":last-child"), ["f"]);
+        assert_eq!(matching(":only-child"), Vec::<String>::new());
+        assert_eq!(matching(":empty"), ["a", "c", "d", "e"]);
+        assert_eq!(matching(":nth-child(odd)"), ["a", "c", "e"]);
+        assert_eq!(matching(":nth-child(2n)"), ["b", "d", "f"]);
+        assert_eq!(matching(":nth-child(3)"), ["c"]);
+        assert_eq!(matching(":nth-child(n+5)"), ["e", "f"]);
+        assert_eq!(matching(":nth-child(-n + 3 of .x)"), ["a", "d", "e"]);
+        assert_eq!(matching("li:nth-of-type(2n - 1)"), ["a", "d", "f"]);
+        assert_eq!(matching(":nth-child(+N+4)"), ["d", "e", "f"]);
+        assert_eq!(matching(":nth-child(-n+ 3)"), ["a", "b", "c"]);
+        assert_eq!(matching(":not(li, #d)"), ["c"]);
+        assert_eq!(matching(":is(p, .x):where(#a, #c)"), ["a", "c"]);
+        assert_eq!(matching(":has(b)"), ["b"]);
+        assert_eq!(matching(":has(> b, + p)"), ["b"]);
+        assert_eq!(matching(":has(~ p)"), ["a", "b"]);
+        assert_eq!(matching(":root > body li:first-child"), ["a"]);
+        assert_eq!(matching("body > li"), Vec::<String>::new());
+        // Extreme values of A and B don't overflow.
+        assert_eq!(matching(":nth-child(-2147483648)"), Vec::<String>::new());
+        assert_eq!(
+            matching("li:nth-child(-2147483648n+2147483647)"),
+            Vec::<String>::new()
+        );
+        assert_eq!(matching("li:nth-child(2147483647n + 1)"), ["a"]);
+
+        let error = parse_css_error("li:hover { color: #000000; }");
+        assert_eq!(
+            (error.expected.as_str(), error.found.as_str()),
+            ("a pseudo-class", "\"hover\"")
+        );
+        // Whitespace is only allowed around the sign before B.
+        for (nth, found) in [
+            ("2x", "2x"),
+            ("2 n", "2 n"),
+            ("3 1", "3 1"),
+            ("2n 1", "2n 1"),
+            ("+ n", "+ n"),
+            ("2n+ +1", "2n+ +1"),
+            ("n- -1", "n- -1"),
+            ("1.5n", "1.5n"),
+            ("", ""),
+        ] {
+            let error = parse_css_error(&format!("li:nth-child({}) {{}}", nth));
+            assert_eq!(
+                (error.expected.as_str(), error.found.as_str()),
+                ("an+b", &*format!("{:?}", found))
+            );
+        }
+    }
+
+    #[test]
+    fn test_generated_content() {
+        let document = Parser::new(
+            "<ol><li title=Intro>a<ol><li>b</li><li>c</li></ol></li><li class=req>d</li></ol>"
+                .to_string(),
+        )
+        .parse_document();
+        let stylesheet = Parser::new(
+            "ol { counter-reset: item; } \
+             li { counter-increment: item; } \
+             li::before { content: counters(item, '.') \") \"; font-size: 10px; } \
+             li[title]:after { content: \" [\" attr(TITLE) \"]\"; } \
+             .req::after { content: \"*\" counter(item, upper-roman); display: block; } \
+             ol ol > li:last-child::before { content: none; }"
+                .to_string(),
+        )
+        .parse_css();
+        assert_eq!(
+            stylesheet.rules[3].declarations[0].value,
+            Value::Content(vec![
+                ContentItem::String(" [".to_string()),
+                ContentItem::Attr("title".to_string()),
+                ContentItem::String("]".to_string()),
+            ])
+        );
+        assert_eq!(stylesheet.rules[3].selectors[0].specificity(), (0, 1, 2));
+
+        let user_agent = Parser::new(USER_AGENT_STYLESHEET.to_string()).parse_css();
+        let styled_root = style_tree(document.document_element(), &[user_agent, stylesheet]);
+        let ol = &styled_root.children[1].children[0];
+        let texts = |node: &StyledNode| -> Vec<String> {
+            node.children
+                .iter()
+                .map(|child| match child.text() {
+                    Some(text) => text.to_string(),
+                    None => child.summary(),
+                })
+                .collect()
+        };
+        let intro = &ol.children[0];
+        assert_eq!(intro.children.len(), 4);
+        assert_eq!(texts(&intro.children[0]), ["1) "]);
+        assert_eq!(
+            intro.children[0].value("font-size"),
+            Some(Value::Length(10.0, Unit::Px))
+        );
+        assert_eq!(texts(&intro.children[3]), [" [Intro]"]);
+        // Counters nest with the lists, and `content: none` generates no box.
+        let nested = &intro.children[2];
+        assert_eq!(texts(&nested.children[0].children[0]), ["1.1) "]);
+        assert_eq!(texts(&nested.children[1]).len(), 1);
+        let required = &ol.children[1];
+        assert_eq!(texts(&required.children[0]), ["2) "]);
+        assert_eq!(texts(&required.children[2]), ["*II"]);
+        assert_eq!(required.children[2].display(), Display::Block);
+
+        // Generated text is laid out like any other text.
+        let layout_root = layout_tree(&styled_root, viewport(800.0));
+        let intro_line = &layout_root.children[0].children[0].children[0].children[0];
+        let before = &intro_line.children[0];
+        assert_eq!(
+            before.get_style_node().summary(),
+            "Before(Element(tag_name: li, attrs: title: Intro, ))"
+        );
+        assert_rect(
+            before.children[0].dimensions.content,
+            48.0,
+            24.0,
+            15.0,
+            12.0,
+        );
+
+        for (css, expected, found) in [
+            ("p::first-line {}", "a pseudo-element", "\"first-line\""),
+            (
+                "p::before span {}",
+                "',' or '{' after a pseudo-element",
+                "\"span\"",
+            ),
+            (
+                "p::after { content: url(x); }",
+                "a string, attr() or counter()",
+                "\"url(x)\"",
+            ),
+        ] {
+            let error = parse_css_error(css);
+            assert_eq!(
+                (error.expected.as_str(), error.found.as_str()),
+                (expected, found)
+            );
+        }
+
+        // Counters saturate instead of overflowing.
+        let document = Parser::new("<p></p><p></p><div></div>".to_string()).parse_document();
+        let stylesheet = Parser::new(
+            "p { counter-increment: c 2147483647; } \
+             div { counter-reset: d -99999999999; counter-increment: d -5; } \
+             p::before { content: counter(c); } div::before { content: counter(d); }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(document.document_element(), &[stylesheet]);
+        let body = &styled_root.children[1];
+        assert_eq!(texts(&body.children[0].children[0]), ["2147483647"]);
+        assert_eq!(texts(&body.children[1].children[0]), ["2147483647"]);
+        assert_eq!(texts(&body.children[2].children[0]), ["-2147483648"]);
+    }
+
+    #[test]
+    fn test_rule_index() {
+        let stylesheet = Parser::new(
+            "#x { margin: 1px; } .a, #x { margin: 2px; } p { margin: 3px; } * { margin: 4px; } \
+             .b.a { margin: 5px; } div p { margin: 6px; } ul.a > li + p { margin: 7px; }"
+                .to_string(),
+        )
+        .parse_css();
+        let index = RuleIndex::new(&stylesheet);
+        let p = elem(
+            "p".to_string(),
+            AttrMap::from(vec![
+                ("id".to_string(), "x".to_string()),
+                ("class".to_string(), "a".to_string()),
+            ]),
+            vec![],
+        );
+        let NodeType::Element(p) = &p.node_type else {
+            unreachable!()
+        };
+        let mut candidates: Vec<_> = index
+            .candidates(p)
+            .map(|candidate| (candidate.position, candidate.specificity))
+            .collect();
+        candidates.sort();
+        // `.b.a` is filed under `.b`, so it doesn't come up.
+        assert_eq!(
+            candidates,
+            [
+                (0, (1, 0, 0)),
+                (1, (0, 1, 0)),
+                (1, (1, 0, 0)),
+                (2, (0, 0, 1)),
+                (3, (0, 0, 0)),
+                (5, (0, 0, 2)),
+                (6, (0, 1, 3)),
+            ]
+        );
+
+        // Only `ul.a` has to be an ancestor, not the sibling `li`.
+        let hashes = selector_hashes(&stylesheet.rules[6].selectors[0]);
+        let ul = elem(
+            "ul".to_string(),
+            AttrMap::from(vec![("class".to_string(), "a".to_string())]),
+            vec![],
+        );
+        let NodeType::Element(ul) = &ul.node_type else {
+            unreachable!()
+        };
+        let mut ancestors = AncestorFilter::new();
+        assert!(!ancestors.might_contain_all(&hashes));
+        ancestors.push(ul);
+        assert!(ancestors.might_contain_all(&hashes));
+        ancestors.pop(ul);
+        assert!(!ancestors.might_contain_all(&hashes));
+
+        // Rules still apply in stylesheet order, whichever bucket they come from.
+        let document = Parser::new("<ul class=a><li></li><p id=x class=a></p></ul>".to_string())
+            .parse_document();
+        let padding = |css: &str| -> Option<Value> {
+            let stylesheet = Parser::new(css.to_string()).parse_css();
+            let styled_root = style_tree(document.document_element(), &[stylesheet]);
+            styled_root.children[1].children[0].children[1].value("padding")
+        };
+        let px = |px| Some(Value::Length(px, Unit::Px));
+        assert_eq!(
+            padding(".a p { padding: 1px; } p.a { padding: 2px; }"),
+            px(2.0)
+        );
+        assert_eq!(
+            padding("p.a { padding: 2px; } .a p { padding: 1px; }"),
+            px(1.0)
+        );
+    }
+
+    // Checks that the rule index and ancestor filter make styling faster than trying every rule
+    // on every element. It's slow in a debug build, so run it with
+    // `cargo test --release bench_style_tree -- --ignored`.
+    #[test]
+    #[ignore]
+    fn bench_style_tree() {
+        // 100 articles, each holding 10 divs with a p of 8 spans: 10,100 elements besides
+        // html, head and body.
+        let mut html = String::new();
+        for i in 0..1000 {
+            if i % 10 == 0 {
+                html += "<article>";
+            }
+            html += &format!(
+                "<div id=d{} class='section s{}'><p class=c{}>",
+                i,
+                i % 50,
+                i % 100
+            );
+            for j in 0..8 {
+                html += &format!("<span class='c{} k{}'>x</span>", j, i % 10);
+            }
+            html += "</p></div>";
+            if i % 10 == 9 {
+                html += "</article>";
+            }
+        }
+        let document = Parser::new(html).parse_document();
+
+        // 5,000 rules, few of which match any given element.
+        let mut css = String::new();
+        for i in 0..5000 {
+            css += &match i % 5 {
+                0 => format!(".c{} {{ margin: 1px; }}\n", i),
+                1 => format!("#d{} p {{ padding: 1px; }}\n", i),
+                2 => format!(
+                    "div.s{} > p .k{} {{ color: #000000; }}\n",
+                    i / 5 % 50,
+                    i / 5 % 10
+                ),
+                3 => format!("article div span.c{} {{ margin: 2px; }}\n", i),
+                _ => format!("ul li.c{} {{ padding: 2px; }}\n", i),
+            };
+        }
+        let stylesheet = Parser::new(css).parse_css();
+
+        let stylesheets = [stylesheet];
+        let start = std::time::Instant::now();
+        let styled_root = style_tree(document.document_element(), &stylesheets);
+        let indexed = start.elapsed();
+        let start = std::time::Instant::now();
+        let unindexed_root = style_tree_unindexed(document.document_element(), &stylesheets);
+        let unindexed = start.elapsed();
+        assert!(
+            indexed <= unindexed,
+            "indexed: {:?}, unindexed: {:?}",
+            indexed,
+            unindexed
+        );
+
+        // Both give the same styles.
+        fn all_values<'a>(node: &'a StyledNode, values: &mut Vec<&'a PropertyMap>) {
+            values.push(&node.specified_values);
+            for child in &node.children {
+                all_values(child, values);
+            }
+        }
+        let (mut expected, mut actual) = (Vec::new(), Vec::new());
+        all_values(&unindexed_root, &mut expected);
+        all_values(&styled_root, &mut actual);
+        assert!(expected == actual, "the styles differ");
+
+        let span = &styled_root.children[1].children[0].children[0].children[0].children[0];
+        assert_eq!(span.value("margin"), Some(Value::Length(1.0, Unit::Px)));
+        assert_eq!(
+            span.value("color"),
+            Some(Value::ColorValue(Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 255
+            }))
+        );
+    }
+
+    fn assert_rect(actual: Rect, x: f32, y: f32, width: f32, height: f32) {
+        let close = |a: f32, b: f32| (a - b).abs() < 0.001;
+        assert!(
+            close(actual.x, x)
+                && close(actual.y, y)
+                && close(actual.width, width)
+                && close(actual.height, height),
+            "expected ({}, {}, {}, {}), got {:?}",
+            x,
+            y,
+            width,
+            height,
+            actual
+        );
+    }
+
+    fn viewport(width: f32) -> Dimensions {
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = width;
+        viewport
+    }
+
+    #[test]
+    fn test_layout_tree_1() -> std::io::Result<()> {
+        let input = fs::read_to_string("input/input1.html")?;
+        let document = Parser::new(input).parse_document();
+        let root = document.document_element();
+        let stylesheet = Parser::new(
+            "head { display: none; } html, body, h1, div, p { display: block; } body { margin: 8px; } h1 { margin: 10px; padding: 5px; } #main { width: 200px; }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(root, &[stylesheet]);
+        let html = layout_tree(&styled_root, viewport(800.0));
+        assert_rect(html.dimensions.content, 0.0, 0.0, 800.0, 84.4);
+
+        let body = &html.children[0];
+        assert_rect(body.dimensions.content, 8.0, 8.0, 784.0, 68.4);
+
+        let h1 = &body.children[0];
+        assert_rect(h1.dimensions.content, 23.0, 23.0, 754.0, 19.2);
+        assert_rect(h1.dimensions.margin_box(), 8.0, 8.0, 784.0, 49.2);
+        assert_rect(
+            h1.children[0].children[0].dimensions.content,
+            23.0,
+            23.0,
+            40.0,
+            19.2,
+        );
+
+        let div = &body.children[1];
+        assert_rect(div.dimensions.content, 8.0, 57.2, 200.0, 19.2);
+        assert_eq!(div.dimensions.margin.right, 584.0);
+
+        // "Hello ", <em>world</em> and "!" share a single line inside an anonymous block.
+        let line = &div.children[0].children[0];
+        assert!(matches!(line.box_type, BoxType::AnonymousBlock));
+        assert_rect(line.children[0].dimensions.content, 8.0, 57.2, 48.0, 19.2);
+        assert_rect(line.children[1].dimensions.content, 56.0, 57.2, 40.0, 19.2);
+        assert_rect(
+            line.children[1].children[0].dimensions.content,
+            56.0,
+            57.2,
+            40.0,
+            19.2,
+        );
+        assert_rect(line.children[2].dimensions.content, 96.0, 57.2, 8.0, 19.2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_tree_2() -> std::io::Result<()> {
+        let input = fs::read_to_string("input/input2.html")?;
+        let document = Parser::new(input).parse_document();
+        let root = document.document_element();
+        let stylesheet = Parser::new(
+            "head, script { display: none; } html, body, h1, p { display: block; } body { margin: 50px; }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(root, &[stylesheet]);
+        let html = layout_tree(&styled_root, viewport(800.0));
+
+        // <head> is not displayed, so <body> is the only child of <html>.
+        assert_eq!(html.children.len(), 1);
+        let body = &html.children[0];
+        assert_rect(body.dimensions.content, 50.0, 50.0, 700.0, 57.6);
+        assert_rect(body.children[0].dimensions.content, 50.0, 50.0, 700.0, 19.2);
+        assert_rect(body.children[1].dimensions.content, 50.0, 69.2, 700.0, 19.2);
+
+        // The button and the hidden script end up in a trailing anonymous block, with the
+        // whitespace around them collapsed to a single space after the button.
+        let line = &body.children[2];
+        assert!(matches!(line.box_type, BoxType::AnonymousBlock));
+        assert_eq!(line.children.len(), 4);
+        assert_rect(line.children[0].dimensions.content, 50.0, 88.4, 0.0, 0.0);
+        assert_rect(line.children[1].dimensions.content, 50.0, 88.4, 64.0, 19.2);
+        assert_rect(line.children[2].dimensions.content, 114.0, 88.4, 8.0, 19.2);
+        assert_rect(line.children[3].dimensions.content, 122.0, 88.4, 0.0, 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_hidden_root() {
+        let document = Parser::new("<p>hidden</p>".to_string()).parse_document();
+        for css in ["html { display: none; }", ":root { display: none; }"] {
+            let stylesheet = Parser::new(css.to_string()).parse_css();
+            let styled_root = style_tree(document.document_element(), &[stylesheet]);
+            let layout_root = layout_tree(&styled_root, viewport(800.0));
+            assert!(matches!(layout_root.box_type, BoxType::AnonymousBlock));
+            assert!(layout_root.children.is_empty());
+            assert_eq!(layout_root.dimensions, Dimensions::default());
+
+            let canvas = paint(&layout_root, viewport(20.0).content);
+            assert_eq!(canvas.width, 20);
+        }
+    }
+
+    #[test]
+    fn test_layout_block_in_inline() {
+        let document = Parser::new(
+            "<div>a <span>b<div>c</div>d</span>e</div><span><div>x</div></span>".to_string(),
+        )
+        .parse_document();
+        let user_agent = Parser::new(USER_AGENT_STYLESHEET.to_string()).parse_css();
+        let styled_root = style_tree(document.document_element(), &[user_agent]);
+        let layout_root = layout_tree(&styled_root, viewport(800.0));
+        let describe = |layout_box: &LayoutBox| -> String {
+            match layout_box.box_type {
+                BoxType::BlockNode(node) => format!("block {}", node.summary()),
+                BoxType::InlineNode(node) => match node.text() {
+                    Some(text) => format!("{:?}", text),
+                    None => format!("inline {}", node.summary()),
+                },
+                BoxType::AnonymousBlock => "anonymous".to_string(),
+            }
+        };
+        let describe_all =
+            |boxes: &[LayoutBox]| -> Vec<String> { boxes.iter().map(describe).collect() };
+
+        // The span is split around the div, which becomes a block between two anonymous blocks.
+        let body = &layout_root.children[0];
+        let outer = &body.children[0];
+        assert_eq!(
+            describe_all(&outer.children),
+            [
+                "anonymous",
+                "block Element(tag_name: div, attrs: )",
+                "anonymous"
+            ]
+        );
+        assert_eq!(
+            describe_all(&outer.children[0].children),
+            ["\"a \"", "inline Element(tag_name: span, attrs: )"]
+        );
+        assert_eq!(
+            describe_all(&outer.children[0].children[1].children),
+            ["\"b\""]
+        );
+        assert_eq!(
+            describe_all(&outer.children[2].children),
+            ["inline Element(tag_name: span, attrs: )", "\"e\""]
+        );
+        assert_rect(outer.children[1].dimensions.content, 8.0, 27.2, 784.0, 19.2);
+
+        // A span holding nothing but a div leaves just the div.
+        assert_eq!(
+            describe_all(&body.children[1..]),
+            ["block Element(tag_name: div, attrs: )"]
+        );
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let document = Parser::new("<p>\n  <em>a</em> b <em>c</em>\n</p>\n<p> </p>".to_string())
+            .parse_document();
+
+        // Text after an element keeps its leading whitespace, and whitespace-only text stays.
+        let body = &document.document_element().children[1];
+        let p = &body.children[0];
+        let texts: Vec<&str> = p
+            .children
+            .iter()
+            .map(|child| match &child.node_type {
+                NodeType::Text(text) => text.as_str(),
+                _ => "<em>",
+            })
+            .collect();
+        assert_eq!(texts, ["\n  ", "<em>", " b ", "<em>", "\n"]);
+        assert_eq!(body.children[1], text("\n".to_string()));
+
+        // Runs of whitespace collapse to one space, and one at the start of a line is dropped.
+        let user_agent = Parser::new(USER_AGENT_STYLESHEET.to_string()).parse_css();
+        let styled_root = style_tree(document.document_element(), &[user_agent]);
+        let layout_root = layout_tree(&styled_root, viewport(800.0));
+        let body = &layout_root.children[0];
+        let line = &body.children[0].children[0];
+        assert_rect(line.children[0].dimensions.content, 8.0, 24.0, 0.0, 0.0);
+        assert_rect(line.children[1].dimensions.content, 8.0, 24.0, 8.0, 19.2);
+        assert_rect(line.children[2].dimensions.content, 16.0, 24.0, 24.0, 19.2);
+        assert_rect(line.children[3].dimensions.content, 40.0, 24.0, 8.0, 19.2);
+
+        // Whitespace between blocks, or alone in one, generates no boxes.
+        assert_eq!(body.children.len(), 2);
+        assert!(body.children[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_paint_1() -> std::io::Result<()> {
+        let input = fs::read_to_string("input/input1.html")?;
+        let document = Parser::new(input).parse_document();
+        let root = document.document_element();
+        let stylesheet = Parser::new(
+            "html, body, div { display: block; } h1 { display: none; } #main { width: 20px; height: 10px; background: #0000ff; border-width: 2px; border-color: #00ff0080; }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(root, &[stylesheet]);
+        let layout_root = layout_tree(&styled_root, viewport(50.0));
+        let canvas = paint(
+            &layout_root,
+            Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 50.0,
+                height: 30.0,
+            },
+        );
+        assert_eq!((canvas.width, canvas.height), (50, 30));
+
+        let pixel = |x: usize, y: usize| canvas.pixels[y * canvas.width + x];
+        let color = |hex: &str| Color::try_from(hex.to_string()).unwrap();
+        // The translucent border is blended over the blue background.
+        assert_eq!(pixel(1, 1), color("#00807fff"));
+        assert_eq!(pixel(23, 13), color("#00807fff"));
+        assert_eq!(pixel(5, 5), color("#0000ff"));
+        // Everything outside the border box keeps the white canvas background.
+        assert_eq!(pixel(24, 5), color("#ffffff"));
+        assert_eq!(pixel(5, 14), color("#ffffff"));
+        Ok(())
+    }
+
+    fn two_pixel_canvas() -> Canvas {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.pixels[1] = Color::try_from("#cc000080".to_string()).unwrap();
+        canvas
+    }
+
+    #[test]
+    fn test_write_ppm() -> std::io::Result<()> {
+        let mut out = Vec::new();
+        write_ppm(&two_pixel_canvas(), &mut out)?;
+        assert_eq!(out, b"P6\n2 1\n255\n\xff\xff\xff\xcc\x00\x00");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_png() -> std::io::Result<()> {
+        let mut out = Vec::new();
+        write_png(&two_pixel_canvas(), &mut out)?;
+
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+        // IHDR: 2x1, 8-bit RGBA.
+        assert_eq!(&out[8..16], b"\0\0\0\x0dIHDR");
+        assert_eq!(&out[16..29], b"\0\0\0\x02\0\0\0\x01\x08\x06\0\0\0");
+        // IDAT: zlib header, one final stored block holding the filtered scanline, Adler-32.
+        assert_eq!(&out[33..41], b"\0\0\0\x14IDAT");
+        assert_eq!(&out[41..48], b"\x78\x01\x01\x09\0\xf6\xff");
+        assert_eq!(&out[48..57], b"\0\xff\xff\xff\xff\xcc\0\0\x80");
+        assert_eq!(&out[57..61], b"\x1d\x9f\x05\x49");
+        // IEND and its well-known CRC.
+        assert_eq!(&out[65..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+        Ok(())
+    }
+
+    fn parse_args(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(&[
+            "-c",
+            "a.css",
+            "input/input1.html",
+            "--css=b.css",
+            "--width",
+            "1024",
+            "-H",
+            "768px",
+            "--mode",
+            "layout",
+        ])
+        .unwrap();
+        assert_eq!(args.html, PathBuf::from("input/input1.html"));
+        assert_eq!(
+            args.css,
+            vec![PathBuf::from("a.css"), PathBuf::from("b.css")]
+        );
+        assert_eq!((args.width, args.height), (1024.0, 768.0));
+        assert_eq!(args.mode, OutputMode::Layout);
+        assert_eq!(args.output, None);
+
+        // Images go to a default file when no output path is given.
+        let args = parse_args(&["page.html"]).unwrap();
+        assert_eq!(args.mode, OutputMode::Image);
+        assert_eq!(args.output, Some(PathBuf::from("output.png")));
+
+        assert!(parse_args(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert_eq!(parse_args(&[]), Err("Missing HTML file".to_string()));
+        assert_eq!(
+            parse_args(&["a.html", "b.html"]),
+            Err("Unexpected argument b.html".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "--css"]),
+            Err("Missing value for --css".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "-w", "wide"]),
+            Err("Invalid size \"wide\"".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "-w", "0"]),
+            Err("Size \"0\" must be at least 1px".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "--height=-5px"]),
+            Err("Size \"-5px\" must be at least 1px".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "-w", "1e12"]),
+            Err("Size \"1e12\" must be at most 16384px".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "-H", "16384"]).unwrap().height,
+            16384.0
+        );
+        assert_eq!(
+            parse_args(&["a.html", "--mode", "pdf"]),
+            Err("Unknown output mode \"pdf\"".to_string())
+        );
+        assert_eq!(
+            parse_args(&["a.html", "--verbose"]),
+            Err("Unknown option --verbose".to_string())
+        );
+    }
+
+    fn parse_css_error(input: &str) -> ParseError {
+        let mut parser = Parser::new(input.to_string());
+        parser.parse_css();
+        assert_eq!(parser.diagnostics.len(), 1, "{:?}", parser.diagnostics);
+        parser.diagnostics.remove(0)
+    }
+
+    fn el(tag_name: &str, attrs: &[(&str, &str)], children: Vec<Node>) -> Node {
+        let attrs = attrs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        elem(tag_name.to_string(), AttrMap::from(attrs), children)
+    }
+
+    fn txt(data: &str) -> Node {
+        text(data.to_string())
+    }
+
+    #[test]
+    fn test_parse_html_implied_and_optional_tags() {
+        let document = Parser::new(
+            "<title>T</title><p>one<p>two<ul><li>a<li>b</ul><table><td>x<td>y</table></div>end"
+                .to_string(),
+        )
+        .parse_document();
+        let root = document.document_element();
+        let expected = el(
+            "html",
+            &[],
+            vec![
+                el("head", &[], vec![el("title", &[], vec![txt("T")])]),
+                el(
+                    "body",
+                    &[],
+                    vec![
+                        el("p", &[], vec![txt("one")]),
+                        el("p", &[], vec![txt("two")]),
+                        el(
+                            "ul",
+                            &[],
+                            vec![el("li", &[], vec![txt("a")]), el("li", &[], vec![txt("b")])],
+                        ),
+                        el(
+                            "table",
+                            &[],
+                            vec![el(
+                                "tbody",
+                                &[],
+                                vec![el(
+                                    "tr",
+                                    &[],
+                                    vec![
+                                        el("td", &[], vec![txt("x")]),
+                                        el("td", &[], vec![txt("y")]),
+                                    ],
+                                )],
+                            )],
+                        ),
+                        // The stray </div> is ignored.
+                        txt("end"),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(*root, expected);
+    }
+
+    #[test]
+    fn test_parse_html_mismatched_end_tags() {
+        let document =
+            Parser::new("</head><meta charset=utf-8><div><span>a</div>b</p>".to_string())
+                .parse_document();
+        let root = document.document_element();
+        let expected = el(
+            "html",
+            &[],
+            vec![
+                // <meta> after <head> was closed still goes into <head>.
+                el(
+                    "head",
+                    &[],
+                    vec![el("meta", &[("charset", "utf-8")], vec![])],
+                ),
+                el(
+                    "body",
+                    &[],
+                    vec![
+                        el("div", &[], vec![el("span", &[], vec![txt("a")])]),
+                        txt("b"),
+                        // A stray </p> creates an empty paragraph.
+                        el("p", &[], vec![]),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(*root, expected);
+
+        // Even an empty document gets html, head and body elements.
+        let document = Parser::new(String::new()).parse_document();
+        let root = document.document_element();
+        assert_eq!(
+            *root,
+            el(
+                "html",
+                &[],
+                vec![el("head", &[], vec![]), el("body", &[], vec![])]
+            )
+        );
+    }
+
+    #[test]
+    fn test_tokenize_html() {
+        let mut tokenizer = Tokenizer::new(
+            "<!DOCTYPE html><P Class=a hidden data-x='y' class=b>x &amp; y&#169;&#x1F600;</p><!-- a - b -->",
+        );
+        let mut tokens = Vec::new();
+        loop {
+            match tokenizer.next_token() {
+                Token::Eof => break,
+                token => tokens.push(token),
+            }
+        }
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Doctype(Doctype {
+                    name: Some("html".to_string()),
+                    ..Default::default()
+                }),
+                Token::StartTag(Tag {
+                    name: "p".to_string(),
+                    attrs: vec![
+                        ("class".to_string(), "a".to_string()),
+                        ("hidden".to_string(), "".to_string()),
+                        ("data-x".to_string(), "y".to_string()),
+                    ],
+                    self_closing: false,
+                }),
+                Token::Text("x & y\u{a9}\u{1F600}".to_string()),
+                Token::EndTag(Tag {
+                    name: "p".to_string(),
+                    ..Default::default()
+                }),
+                Token::Comment(" a - b ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_html_unquoted_and_boolean_attributes() {
+        let document = Parser::new(
+            "<!doctype html><html><input type=checkbox checked><label for='x'>A</label></input></html>"
+                .to_string(),
+        )
+        .parse_document();
+        let root = document.document_element();
+        let input = el("input", &[("type", "checkbox"), ("checked", "")], vec![]);
+        let label = el("label", &[("for", "x")], vec![txt("A")]);
+        assert_eq!(
+            *root,
+            el(
+                "html",
+                &[],
+                vec![el("head", &[], vec![]), el("body", &[], vec![input, label])]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_html_void_elements() {
+        let document = Parser::new(
+            "<p>a<br>b<img src=\"x.png\" alt=''><hr/>c<input disabled/></input><div/>d</div>"
+                .to_string(),
+        )
+        .parse_document();
+        let root = document.document_element();
+        let body = el(
+            "body",
+            &[],
+            vec![
+                el(
+                    "p",
+                    &[],
+                    vec![
+                        txt("a"),
+                        el("br", &[], vec![]),
+                        txt("b"),
+                        el("img", &[("src", "x.png"), ("alt", "")], vec![]),
+                    ],
+                ),
+                el("hr", &[], vec![]),
+                txt("c"),
+                el("input", &[("disabled", "")], vec![]),
+                // `/>` on a non-void element is ignored, so the div keeps its contents.
+                el("div", &[], vec![txt("d")]),
+            ],
+        );
+        assert_eq!(*root, el("html", &[], vec![el("head", &[], vec![]), body]));
+    }
+
+    #[test]
+    fn test_parse_html_character_references() {
+        let document = Parser::new(
+            "<p title=\"&lt;&quot;tom&quot; &amp jerry&gt;\" data-url='?a=1&copy=2&amp;lang=en'>\
+             Fish &amp; Chips&nbsp;&#169; &#xA9;&#x1F600; &notin; &notit; &copy &#128; &#0; &bogus; &</p>"
+                .to_string(),
+        )
+        .parse_document();
+        let root = document.document_element();
+        let p = el(
+            "p",
+            &[
+                ("title", "<\"tom\" & jerry>"),
+                ("data-url", "?a=1&copy=2&lang=en"),
+            ],
+            vec![txt(
+                "Fish & Chips\u{a0}\u{a9} \u{a9}\u{1F600} \u{2209} \u{ac}it; \u{a9} \u{20ac} \u{fffd} &bogus; &",
+            )],
+        );
+        assert_eq!(root.children[1].children[0], p);
+    }
+
+    #[test]
+    fn test_parse_html_comments_and_doctype() {
+        let document = Parser::new(
+            "<!-- before --><!DOCTYPE html><p>a<!-- x -- y->z --><![CDATA[c]]>\
+             <svg><![CDATA[<b>]]]></svg><table></table></html><!--after-->"
+                .to_string(),
+        )
+        .parse_document();
+        assert_eq!(document.mode, QuirksMode::NoQuirks);
+        assert_eq!(document.children.len(), 4);
+        assert_eq!(document.children[0], comment(" before ".to_string()));
+        assert!(
+            document.children[1].node_type
+                == NodeType::Doctype(DoctypeData {
+                    name: "html".to_string(),
+                    public_id: String::new(),
+                    system_id: String::new(),
+                })
+        );
+        assert_eq!(document.children[3], comment("after".to_string()));
+
+        let p = el(
+            "p",
+            &[],
+            vec![
+                txt("a"),
+                comment(" x -- y->z ".to_string()),
+                // CDATA is only allowed in SVG and MathML; in HTML it becomes a comment.
+                comment("[CDATA[c]]".to_string()),
+                el("svg", &[], vec![txt("<b>]")]),
+            ],
+        );
+        let body = &document.document_element().children[1];
+        // In standards mode a table closes the paragraph.
+        assert_eq!(body.children, vec![p, el("table", &[], vec![])]);
+
+        // A comment after `</body>` follows `body` in `html`, even when text after it still goes
+        // into `body`.
+        let document =
+            Parser::new("<body></body><!-- c -->t</html><!-- d -->".to_string()).parse_document();
+        let html = document.document_element();
+        assert_eq!(html.children.len(), 3);
+        assert_eq!(html.children[1], el("body", &[], vec![txt("t")]));
+        assert_eq!(html.children[2], comment(" c ".to_string()));
+        assert_eq!(document.children[1], comment(" d ".to_string()));
+
+        // Without a DOCTYPE, or with a legacy one, the document is in quirks mode and the table
+        // stays inside the paragraph.
+        let document = Parser::new("<p><table></table>".to_string()).parse_document();
+        assert_eq!(document.mode, QuirksMode::Quirks);
+        let body = &document.document_element().children[1];
+        assert_eq!(
+            body.children,
+            vec![el("p", &[], vec![el("table", &[], vec![])])]
+        );
+
+        let mode = |input: &str| Parser::new(input.to_string()).parse_document().mode;
+        assert_eq!(
+            mode("<!DOCTYPE HTML PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">"),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">"),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">"),
+            QuirksMode::NoQuirks
+        );
+        assert_eq!(mode("<!DOCTYPE svg>"), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn test_parse_html_raw_text() {
+        let script = "   if (a < b && c </scripts>) { x = \"</div>\" } \
+                      <!-- <script>s = 1</script> -->";
+        let document = Parser::new(format!(
+            "<title> A &amp; <b>B</b> </title><style>p > a {{ color: red }}</style>\
+             <script>{}</SCRIPT><textarea>  <p>&lt;hi&gt;</textarea><plaintext></plaintext><p>",
+            script
+        ))
+        .parse_document();
+        let head = el(
+            "head",
+            &[],
+            vec![
+                // RCDATA: character references are decoded, but tags are not.
+                // Leading whitespace is kept, like the rest of the text.
+                el("title", &[], vec![txt(" A & <b>B</b> ")]),
+                // Raw text: read as-is up to the matching end tag.
+                el("style", &[], vec![txt("p > a { color: red }")]),
+                el("script", &[], vec![txt(script)]),
+            ],
+        );
+        let body = el(
+            "body",
+            &[],
+            vec![
+                el("textarea", &[], vec![txt("  <p><hi>")]),
+                el("plaintext", &[], vec![txt("</plaintext><p>")]),
+            ],
+        );
+        assert_eq!(
+            *document.document_element(),
+            el("html", &[], vec![head, body])
+        );
+    }
+
+    #[test]
+    fn test_style_sources() -> std::io::Result<()> {
+        let document = Parser::new(
+            "<link rel='Alternate stylesheet' href=alt.css><link rel=icon href=i.png>\
+             <link rel='preload stylesheet' href='css/a.css?v=2'><style>p { color: #ff0000; }</style>\
+             <style type=text/plain>x</style><link rel=stylesheet href=https://example.com/b.css>\
+             <link rel=STYLESHEET href=/abs/c.css><body><template><style>t {}</style></template>\
+             <style>div {}</style>"
+                .to_string(),
+        )
+        .parse_document();
+        assert_eq!(
+            style_sources(document.document_element(), Path::new("site")),
+            vec![
+                StyleSource::Link(PathBuf::from("site/css/a.css")),
+                StyleSource::Inline("p { color: #ff0000; }".to_string()),
+                StyleSource::Link(PathBuf::from("/abs/c.css")),
+                StyleSource::Inline("div {}".to_string()),
+            ]
+        );
+
+        // The `<style>` in input2.html holds a complete stylesheet.
+        let input = fs::read_to_string("input/input2.html")?;
+        let document = Parser::new(input).parse_document();
+        let sources = style_sources(document.document_element(), Path::new("input"));
+        assert_eq!(sources.len(), 1);
+        let StyleSource::Inline(css) = &sources[0] else {
+            panic!("expected an inline stylesheet");
+        };
+        assert_eq!(Parser::new(css.clone()).parse_css().rules.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_style_attribute_errors() {
+        let document = Parser::new(
+            "<div style='color: #zz; margin: 1px'><p style='margin: 2px'></p>\
+             <p style='padding: 1em'></p></div>"
+                .to_string(),
+        )
+        .parse_document();
+        let errors: Vec<(String, usize, String)> =
+            style_attribute_errors(document.document_element())
+                .into_iter()
+                .map(|(tag_name, error)| (tag_name, error.column, error.expected))
+                .collect();
+        assert_eq!(
+            errors,
+            [
+                ("div".to_string(), 8, "a color".to_string()),
+                ("p".to_string(), 10, "\"px\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_css_errors() {
+        let error = parse_css_error("h1 { margin: auto; }\np { color: #zz0000; }");
+        assert_eq!((error.line, error.column), (2, 12));
+        assert_eq!(error.expected, "a color");
+        assert_eq!(error.found, "\"#zz0000\"");
+
+        let error = parse_css_error("div) p { margin: 0px; }");
+        assert_eq!(error.expected, "',' or '{' in selector list");
+        assert_eq!(error.found, "')'");
+
+        let error = parse_css_error("div > { margin: 0px; }");
+        assert_eq!(error.expected, "a selector");
+        assert_eq!(error.found, "'{'");
+
+        let error = parse_css_error("p { padding: 10em; }");
+        assert_eq!(error.expected, "\"px\"");
+
+        let error = parse_css_error("p { margin: auto !importnt; }");
+        assert_eq!(error.expected, "\"important\"");
+        assert_eq!(error.found, "\"importnt\"");
+
+        let error = parse_css_error("p { padding: 10px margin: 0px }");
+        assert_eq!((error.line, error.column), (1, 25));
+        assert_eq!(error.expected, "a value");
+        assert_eq!(error.found, "':'");
+    }
+
+    #[test]
+    fn test_load_stylesheets() -> std::io::Result<()> {
+        let document = Parser::new(
+            "<link rel=stylesheet href=missing.css><style>p { margin: 1px; }</style>".to_string(),
+        )
+        .parse_document();
+        let args = parse_args(&["no-such-dir/page.html"]).unwrap();
+        // The missing sheet is skipped; the user-agent sheet and the `<style>` sheet remain.
+        let stylesheets = load_stylesheets(&args, document.document_element())?;
+        assert_eq!(stylesheets.len(), 2);
+        assert_eq!(stylesheets[1].rules.len(), 1);
+
+        // A missing sheet given on the command line is still an error.
+        let args = parse_args(&["-c", "no-such-dir/user.css", "page.html"]).unwrap();
+        assert!(load_stylesheets(&args, document.document_element()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_css_error_recovery() {
+        let mut parser = Parser::new(
+            "h1 { color: #zz0000; margin: 1px }\n\
+             h2 { padding: 2em; margin: 2px; @page { x: y } padding: 3px }\n\
+             @unknown foo { h3 { margin: 4px } }\n\
+             h4) { margin: 5px; } h5 { margin: 6px; content: attr(); x: [}] }\n\
+             h6 { margin: 7px"
+                .to_string(),
+        );
+        let stylesheet = parser.parse_css();
+        let summary: Vec<(String, Vec<(String, Value)>)> = stylesheet
+            .rules
+            .iter()
+            .map(|rule| {
+                let selector = rule.selectors[0].rightmost().tag_name.clone().unwrap();
+                let declarations = rule
+                    .declarations
+                    .iter()
+                    .map(|d| (d.name.clone(), d.value.clone()))
+                    .collect();
+                (selector, declarations)
+            })
+            .collect();
+        let px = |px| Value::Length(px, Unit::Px);
+        assert_eq!(
+            summary,
+            vec![
+                ("h1".to_string(), vec![("margin".to_string(), px(1.0))]),
+                (
+                    "h2".to_string(),
+                    vec![
+                        ("margin".to_string(), px(2.0)),
+                        ("padding".to_string(), px(3.0))
+                    ]
+                ),
+                // The `}` inside the brackets doesn't end the block.
+                (
+                    "h5".to_string(),
+                    vec![
+                        ("margin".to_string(), px(6.0)),
+                        ("x".to_string(), Value::Keyword("[}]".to_string()))
+                    ]
+                ),
+                ("h6".to_string(), vec![("margin".to_string(), px(7.0))]),
+            ]
+        );
+        let diagnostics: Vec<(usize, usize, &str)> = parser
+            .diagnostics
+            .iter()
+            .map(|d| (d.line, d.column, d.expected.as_str()))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (1, 13, "a color"),
+                (2, 15, "\"px\""),
+                (4, 3, "',' or '{' in selector list"),
+                (4, 54, "an identifier"),
+                (5, 17, "'}'"),
+            ]
+        );
+
+        // Bad declarations in a `style` attribute are dropped on their own.
+        let mut parser = Parser::new("color: #0; margin: 1px; } ; padding: 2px !x".to_string());
+        let declarations = parser.parse_style_attribute();
+        assert_eq!(declarations.len(), 1);
+        assert_eq!(declarations[0].value, px(1.0));
+        assert_eq!(parser.diagnostics.len(), 3);
+
+        // The user-agent stylesheet has no errors.
+        let mut parser = Parser::new(USER_AGENT_STYLESHEET.to_string());
+        parser.parse_css();
+        assert_eq!(parser.diagnostics, []);
+    }
+
+    #[test]
+    fn test_list_values() {
+        let stylesheet = Parser::new(
+            "p { font-family: \"Times New Roman\", Times, serif; \
+                 font: italic 12px/1.5 serif; \
+                 width: calc(1px + 2px); \
+                 border: 1px solid #000; \
+                 background: #f808; }"
+                .to_string(),
+        )
+        .parse_css();
+        let color = |r, g, b, a| Value::ColorValue(Color { r, g, b, a });
+        let keyword = |keyword: &str| Value::Keyword(keyword.to_string());
+        let px = |px| Value::Length(px, Unit::Px);
+        let values: Vec<&Value> = stylesheet.rules[0]
+            .declarations
+            .iter()
+            .map(|declaration| &declaration.value)
+            .collect();
+        assert_eq!(
+            values,
+            [
+                &Value::List(
+                    vec![
+                        Value::String("Times New Roman".to_string()),
+                        keyword("Times"),
+                        keyword("serif"),
+                    ],
+                    Separator::Comma
+                ),
+                &Value::List(
+                    vec![
+                        keyword("italic"),
+                        px(12.0),
+                        keyword("/"),
+                        Value::Number(1.5),
+                        keyword("serif"),
+                    ],
+                    Separator::Space
+                ),
+                &keyword("calc(1px + 2px)"),
+                // `#000` is short for `#000000`, and `#f808` for `#ff880088`.
+                &Value::List(
+                    vec![px(1.0), keyword("solid"), color(0, 0, 0, 255)],
+                    Separator::Space
+                ),
+                &color(255, 136, 0, 136),
+            ]
+        );
+
+        // Shorthands with several values set their longhands.
+        let document = Parser::new("<div id=a></div><div id=b></div><p id=c></p>".to_string())
+            .parse_document();
+        let stylesheet = Parser::new(
+            "html, body, div { display: block; } head { display: none; } #a { margin: 1px 2px 3px; border: #f00 4px solid; } \
+             #b { margin-top: 5px; padding: 1px 2px; margin: 7px 8px; border: 1px; border: none; } \
+             #c { counter-reset: x 2 y; } \
+             #c::before { content: counter(x) \",\" counter(y); }"
+                .to_string(),
+        )
+        .parse_css();
+        let styled_root = style_tree(document.document_element(), &[stylesheet]);
+        let body = &styled_root.children[1];
+        let sides = |node: &StyledNode, name: &str| -> Vec<Option<Value>> {
+            ["top", "right", "bottom", "left"]
+                .iter()
+                .map(|side| node.value(&format!("{}-{}", name, side)))
+                .collect()
+        };
+        let a = &body.children[0];
+        assert_eq!(
+            sides(a, "margin"),
+            [Some(px(1.0)), Some(px(2.0)), Some(px(3.0)), Some(px(2.0))]
+        );
+        assert_eq!(
+            (a.value("border-left-width"), a.value("border-color")),
+            (
+                Some(px(4.0)),
+                Some(Value::ColorValue(
+                    Color::try_from("#ff0000".to_string()).unwrap()
+                ))
+            )
+        );
+        let b = &body.children[1];
+        assert_eq!(
+            sides(b, "margin"),
+            [Some(px(7.0)), Some(px(8.0)), Some(px(7.0)), Some(px(8.0))]
+        );
+        assert_eq!(
+            sides(b, "padding"),
+            [Some(px(1.0)), Some(px(2.0)), Some(px(1.0)), Some(px(2.0))]
+        );
+        assert_eq!(b.value("border-top-width"), Some(px(0.0)));
+
+        let c = &body.children[2];
+        assert_eq!(c.children[0].children[0].text(), Some("2,0"));
+
+        // Layout uses the longhands.
+        let layout_root = layout_tree(&styled_root, viewport(800.0));
+        let a = &layout_root.children[0].children[0];
+        assert_eq!(a.dimensions.margin.left, 2.0);
+        assert_eq!(a.dimensions.border.top, 4.0);
+        assert_rect(a.dimensions.content, 6.0, 5.0, 788.0, 0.0);
+    }
+
+    #[test]
+    fn test_css_tokenizer() {
+        let tokens: Vec<CssToken> =
+            tokenize("a/* x */#b:nth-child(2n+1){x:'\\41 \\'';y:-1.5e2px 10%}")
+                .into_iter()
+                .map(|spanned| spanned.token)
+                .collect();
+        let number = |value, integer| Number { value, integer };
+        assert_eq!(
+            tokens,
+            vec![
+                CssToken::Ident("a".to_string()),
+                CssToken::Hash {
+                    value: "b".to_string(),
+                    is_id: true
+                },
+                CssToken::Colon,
+                CssToken::Function("nth-child".to_string()),
+                CssToken::Dimension(number(2.0, true), "n".to_string()),
+                CssToken::Number(number(1.0, true)),
+                CssToken::RightParen,
+                CssToken::LeftBrace,
+                CssToken::Ident("x".to_string()),
+                CssToken::Colon,
+                CssToken::String("A'".to_string()),
+                CssToken::Semicolon,
+                CssToken::Ident("y".to_string()),
+                CssToken::Colon,
+                CssToken::Dimension(number(-150.0, false), "px".to_string()),
+                CssToken::Whitespace,
+                CssToken::Percentage(number(10.0, true)),
+                CssToken::RightBrace,
+            ]
+        );
+
+        // Comments, strings with braces in them, escapes, at-rules with nested blocks and
+        // HTML comment markers around the stylesheet.
+        let stylesheet = Parser::new(
+            "<!-- @import url(a.css);\n\
+             @media screen { p { margin: 9px; } }\n\
+             /* p { margin: 8px; } */\n\
+             p::before { content: \"} {\\\"\"; margin: 1px; }\n\
+             .\\31 st { margin: 2px; } -->"
+                .to_string(),
+        )
+        .parse_css();
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(
+            stylesheet.rules[0].declarations[0].value,
+            Value::Content(vec![ContentItem::String("} {\"".to_string())])
+        );
+        assert_eq!(
+            stylesheet.rules[0].declarations[1].value,
+            Value::Length(1.0, Unit::Px)
+        );
+        assert_eq!(
+            stylesheet.rules[1].selectors[0].rightmost().class,
+            vec!["1st".to_string()]
+        );
+    }
 }
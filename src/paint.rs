@@ -0,0 +1,174 @@
+use crate::css::{Color, Value};
+use crate::layout::{BoxType, LayoutBox, Rect};
+
+// The background every canvas starts out with.
+const WHITE: Color = Color {
+    r: 255,
+    g: 255,
+    b: 255,
+    a: 255,
+};
+
+pub(crate) type DisplayList = Vec<DisplayCommand>;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DisplayCommand {
+    SolidColor(Color, Rect),
+}
+
+// A grid of pixels, stored in row-major order.
+pub(crate) struct Canvas {
+    pub(crate) pixels: Vec<Color>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+// Paint a tree of LayoutBoxes to an array of pixels.
+pub(crate) fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
+    let display_list = build_display_list(layout_root);
+    let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
+    for item in display_list {
+        canvas.paint_item(&item);
+    }
+    canvas
+}
+
+pub(crate) fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
+    let mut list = Vec::new();
+    render_layout_box(&mut list, layout_root);
+    list
+}
+
+fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
+    render_background(list, layout_box);
+    render_borders(list, layout_box);
+    for child in &layout_box.children {
+        render_layout_box(list, child);
+    }
+}
+
+fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let background =
+        get_color(layout_box, "background-color").or_else(|| get_color(layout_box, "background"));
+    if let Some(color) = background {
+        list.push(DisplayCommand::SolidColor(
+            color,
+            layout_box.dimensions.border_box(),
+        ));
+    }
+}
+
+fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let color = match get_color(layout_box, "border-color") {
+        Some(color) => color,
+        _ => return, // bail out if no border-color is specified
+    };
+
+    let d = &layout_box.dimensions;
+    let border_box = d.border_box();
+
+    // Left border
+    list.push(DisplayCommand::SolidColor(
+        color,
+        Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: d.border.left,
+            height: border_box.height,
+        },
+    ));
+
+    // Right border
+    list.push(DisplayCommand::SolidColor(
+        color,
+        Rect {
+            x: border_box.x + border_box.width - d.border.right,
+            y: border_box.y,
+            width: d.border.right,
+            height: border_box.height,
+        },
+    ));
+
+    // Top and bottom borders stop short of the left and right ones, so translucent corners are
+    // not painted twice.
+    let inner_x = border_box.x + d.border.left;
+    let inner_width = border_box.width - d.border.left - d.border.right;
+
+    // Top border
+    list.push(DisplayCommand::SolidColor(
+        color,
+        Rect {
+            x: inner_x,
+            y: border_box.y,
+            width: inner_width,
+            height: d.border.top,
+        },
+    ));
+
+    // Bottom border
+    list.push(DisplayCommand::SolidColor(
+        color,
+        Rect {
+            x: inner_x,
+            y: border_box.y + border_box.height - d.border.bottom,
+            width: inner_width,
+            height: d.border.bottom,
+        },
+    ));
+}
+
+// Return the specified color for CSS property `name`, or None if no color was specified.
+fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+    match layout_box.box_type {
+        BoxType::BlockNode(style) | BoxType::InlineNode(style) => match style.value(name) {
+            Some(Value::ColorValue(color)) => Some(color),
+            _ => None,
+        },
+        BoxType::AnonymousBlock => None,
+    }
+}
+
+impl Canvas {
+    // Create a blank canvas
+    pub(crate) fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            pixels: vec![WHITE; width * height],
+            width,
+            height,
+        }
+    }
+
+    fn paint_item(&mut self, item: &DisplayCommand) {
+        match *item {
+            DisplayCommand::SolidColor(color, rect) => {
+                // Clip the rectangle to the canvas boundaries.
+                let x0 = rect.x.clamp(0.0, self.width as f32) as usize;
+                let y0 = rect.y.clamp(0.0, self.height as f32) as usize;
+                let x1 = (rect.x + rect.width).clamp(0.0, self.width as f32) as usize;
+                let y1 = (rect.y + rect.height).clamp(0.0, self.height as f32) as usize;
+
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        let pixel = &mut self.pixels[y * self.width + x];
+                        *pixel = blend(color, *pixel);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Composite `src` over `dst` using the source alpha channel.
+fn blend(src: Color, dst: Color) -> Color {
+    if src.a == 255 {
+        return src;
+    }
+    let alpha = src.a as f32 / 255.0;
+    let mix = |s: u8, d: u8| (s as f32 * alpha + d as f32 * (1.0 - alpha)).round() as u8;
+    Color {
+        r: mix(src.r, dst.r),
+        g: mix(src.g, dst.g),
+        b: mix(src.b, dst.b),
+        a: (src.a as f32 + dst.a as f32 * (1.0 - alpha)).round() as u8,
+    }
+}
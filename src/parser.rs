@@ -1,292 +1,913 @@
-use crate::css::{Color, Declaration, Rule, Selector, SimpleSelector, Stylesheet, Unit, Value};
-use crate::html::{elem, text, AttrMap, Node};
-use std::collections::HashMap;
+use crate::css::{
+    AttributeOperator, AttributeSelector, Color, Combinator, ContentItem, Declaration, Nth, Origin,
+    PseudoClass, PseudoElement, RelativeSelector, Rule, Selector, Separator, SimpleSelector,
+    Stylesheet, Unit, Value,
+};
+use crate::css_tokenizer::{tokenize, Spanned, Token};
+use crate::html::Document;
+use crate::tree_builder::TreeBuilder;
+use std::fmt::{Display, Formatter};
+
+// An error encountered while parsing, with the position where it happened.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParseError {
+    // Byte offset into the input.
+    pub(crate) pos: usize,
+    // 1-based line and column (in characters) of `pos`.
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) expected: String,
+    pub(crate) found: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: expected {} but found {}",
+            self.line, self.column, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(error: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+pub(crate) type ParseResult<T> = Result<T, ParseError>;
 
 pub struct Parser {
-    pos: usize,
     input: String,
+    // The tokens of `input`, when it's parsed as CSS.
+    tokens: Vec<Spanned>,
+    // The index of the next token.
+    next: usize,
+    // The errors found while parsing CSS. The parser recovers from each of them by leaving out
+    // the declaration or rule it was in, the way browsers do.
+    pub(crate) diagnostics: Vec<ParseError>,
 }
 
 impl Parser {
     pub(crate) fn new(input: String) -> Self {
-        Self { pos: 0, input }
+        Self {
+            input,
+            tokens: Vec::new(),
+            next: 0,
+            diagnostics: Vec::new(),
+        }
     }
-    // Read the current character without consuming it.
-    fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+
+    // Read the next token without consuming it, or `None` at the end of the input.
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|spanned| &spanned.token)
     }
 
-    // Do the next characters start with the given string?
-    fn starts_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+    // Consume the next token.
+    fn consume(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.next += 1;
+        token
     }
 
-    // If the exact string `s` is found at the current position, consume it.
-    // Otherwise, panic.
-    fn expect(&mut self, s: &str) {
-        if self.starts_with(s) {
-            self.pos += s.len();
-        } else {
-            panic!("Expected {:?} at byte {} but it was not found", s, self.pos);
+    // The byte offset of the next token.
+    fn pos(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.input.len(), |spanned| spanned.start)
+    }
+
+    // The text the token at `index` came from.
+    fn source(&self, index: usize) -> &str {
+        let spanned = &self.tokens[index];
+        &self.input[spanned.start..spanned.end]
+    }
+
+    // Describe the token at `index` for an error message.
+    fn describe(&self, index: usize) -> String {
+        match self.tokens.get(index).map(|spanned| &spanned.token) {
+            None => "end of input".to_string(),
+            Some(Token::Whitespace) => "whitespace".to_string(),
+            Some(
+                Token::Delim(_)
+                | Token::Colon
+                | Token::Semicolon
+                | Token::Comma
+                | Token::LeftBracket
+                | Token::RightBracket
+                | Token::LeftParen
+                | Token::RightParen
+                | Token::LeftBrace
+                | Token::RightBrace,
+            ) => format!("{:?}", self.source(index).chars().next().unwrap_or(' ')),
+            Some(_) => format!("{:?}", self.source(index)),
         }
     }
 
-    // Return true if all input is consumed.
-    fn eof(&self) -> bool {
-        self.pos >= self.input.len()
+    // Build an error at the next token, describing what was found there.
+    fn error(&self, expected: impl Into<String>) -> ParseError {
+        self.error_at(self.pos(), expected, self.describe(self.next))
     }
 
-    // Return the current character, and advance self.pos to the next character.
-    fn consume_char(&mut self) -> char {
-        let c = self.next_char();
-        self.pos += c.len_utf8();
-        c
+    // Build an error at byte offset `pos`.
+    fn error_at(
+        &self,
+        pos: usize,
+        expected: impl Into<String>,
+        found: impl Into<String>,
+    ) -> ParseError {
+        let before = &self.input[..pos];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            pos,
+            line,
+            column: before[line_start..].chars().count() + 1,
+            expected: expected.into(),
+            found: found.into(),
+        }
+    }
+
+    // Is the next token the exact text `s`, like `;` or `{`?
+    fn at(&self, s: &str) -> bool {
+        self.next < self.tokens.len() && self.source(self.next) == s
     }
 
-    // Consume characters until `test` returns false.
-    fn consume_while(&mut self, test: impl Fn(char) -> bool) -> String {
-        let mut result = String::new();
-        while !self.eof() && test(self.next_char()) {
-            result.push(self.consume_char());
+    // If the next token is the exact text `s`, consume it. Otherwise, return an error.
+    fn expect(&mut self, s: &str) -> ParseResult<()> {
+        if self.at(s) {
+            self.next += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("{:?}", s)))
         }
-        result
     }
 
-    // Consume and discard zero or more whitespace characters.
-    fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+    // Consume any whitespace, returning whether there was some.
+    fn consume_whitespace(&mut self) -> bool {
+        let start = self.next;
+        while self.peek() == Some(&Token::Whitespace) {
+            self.next += 1;
+        }
+        self.next > start
     }
 
-    // Parse a tag or attribute name.
-    fn parse_name(&mut self) -> String {
-        self.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9'))
+    // Consume a component value: a single token, or a whole block or function including its
+    // contents.
+    // https://www.w3.org/TR/css-syntax-3/#consume-component-value
+    fn consume_component_value(&mut self) {
+        let close = match self.consume() {
+            Some(Token::LeftBrace) => Token::RightBrace,
+            Some(Token::LeftBracket) => Token::RightBracket,
+            Some(Token::LeftParen | Token::Function(_)) => Token::RightParen,
+            _ => return,
+        };
+        while let Some(token) = self.peek() {
+            if *token == close {
+                self.next += 1;
+                return;
+            }
+            self.consume_component_value();
+        }
     }
 
-    // Parse a single node.
-    fn parse_node(&mut self) -> Node {
-        if self.starts_with("<") {
-            if self.starts_with("<!--") {
-                self.parse_comment();
-                self.parse_node()
-            } else {
-                self.parse_element()
+    // Parse an HTML document, including its DOCTYPE and any comments around the `html`
+    // element. This can't fail: like a browser, the tree builder makes a document out of any
+    // input.
+    pub(crate) fn parse_document(&mut self) -> Document {
+        TreeBuilder::new(&self.input).build()
+    }
+
+    // Parse one simple selector, e.g.: `type#id.class1.class2.class3[attr=value]:first-child::before`
+    fn parse_simple_selector(&mut self) -> ParseResult<SimpleSelector> {
+        let start = self.next;
+        let mut selector = SimpleSelector {
+            tag_name: None,
+            id: None,
+            class: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
+        };
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LeftBracket => selector.attributes.push(self.parse_attribute_selector()?),
+                Token::Colon => {
+                    if let Some(pseudo_element) = self.parse_pseudo_element()? {
+                        selector.pseudo_element = Some(pseudo_element);
+                        // Nothing can follow a pseudo-element.
+                        break;
+                    }
+                    selector.pseudo_classes.push(self.parse_pseudo_class()?);
+                }
+                Token::Hash { value, is_id: true } => {
+                    selector.id = Some(value.clone());
+                    self.next += 1;
+                }
+                Token::Hash { is_id: false, .. } => return Err(self.error("an identifier")),
+                Token::Delim('.') => {
+                    self.next += 1;
+                    selector.class.push(self.parse_identifier()?);
+                }
+                Token::Delim('*') => {
+                    // universal selector
+                    self.next += 1;
+                }
+                Token::Ident(name) => {
+                    // Tag names are case-insensitive in HTML, and the DOM has them in lower case.
+                    selector.tag_name = Some(name.to_ascii_lowercase());
+                    self.next += 1;
+                }
+                _ => break,
             }
-        } else {
-            self.parse_text()
         }
+        if self.next == start {
+            return Err(self.error("a selector"));
+        }
+        Ok(selector)
     }
 
-    // Parse a comment (skip and ignore)
-    fn parse_comment(&mut self) {
-        self.expect("<!--");
-        // TODO: Your comment can't have a dash in it or my parser will break
-        let comment = self.consume_while(|c| c != '-');
-        self.expect("-->");
+    // Parse an attribute selector, e.g. `[lang]`, `[href^="https:"]` or `[type=a i]`.
+    fn parse_attribute_selector(&mut self) -> ParseResult<AttributeSelector> {
+        self.expect("[")?;
         self.consume_whitespace();
-        println!("Parsed comment: {}", comment);
+        // Attribute names are case-insensitive in HTML, and the DOM stores them in lower case.
+        let name = self.parse_identifier()?.to_ascii_lowercase();
+        self.consume_whitespace();
+        let operator = match self.peek() {
+            Some(Token::Delim('=')) => Some(AttributeOperator::Equals),
+            Some(Token::Delim('~')) => Some(AttributeOperator::Includes),
+            Some(Token::Delim('|')) => Some(AttributeOperator::DashMatch),
+            Some(Token::Delim('^')) => Some(AttributeOperator::Prefix),
+            Some(Token::Delim('$')) => Some(AttributeOperator::Suffix),
+            Some(Token::Delim('*')) => Some(AttributeOperator::Substring),
+            _ => None,
+        };
+        let mut selector = AttributeSelector {
+            name,
+            matcher: None,
+            case_insensitive: false,
+        };
+        if let Some(operator) = operator {
+            if operator != AttributeOperator::Equals {
+                self.next += 1;
+            }
+            self.expect("=")?;
+            self.consume_whitespace();
+            let value = match self.peek() {
+                Some(Token::String(value)) => {
+                    let value = value.clone();
+                    self.next += 1;
+                    value
+                }
+                // Browsers want an identifier here, but we also take things like `.pdf`.
+                _ => {
+                    let mut value = String::new();
+                    while !matches!(
+                        self.peek(),
+                        None | Some(Token::Whitespace | Token::RightBracket)
+                    ) {
+                        value += self.source(self.next);
+                        self.next += 1;
+                    }
+                    if value.is_empty() {
+                        return Err(self.error("an attribute value"));
+                    }
+                    value
+                }
+            };
+            selector.matcher = Some((operator, value));
+            self.consume_whitespace();
+            // An optional flag: `i` to compare case-insensitively, `s` for the default.
+            if let Some(Token::Ident(flag)) = self.peek() {
+                if flag.eq_ignore_ascii_case("i") || flag.eq_ignore_ascii_case("s") {
+                    selector.case_insensitive = flag.eq_ignore_ascii_case("i");
+                    self.next += 1;
+                    self.consume_whitespace();
+                }
+            }
+        }
+        if self.peek() != Some(&Token::RightBracket) {
+            return Err(self.error("']' after attribute selector"));
+        }
+        self.next += 1;
+        Ok(selector)
     }
 
-    // Parse a text node.
-    fn parse_text(&mut self) -> Node {
-        text(self.consume_while(|c| c != '<'))
+    // Parse `::before` or `::after`, or their legacy forms `:before` and `:after`. If this is a
+    // pseudo-class instead, return `None` without consuming anything.
+    fn parse_pseudo_element(&mut self) -> ParseResult<Option<PseudoElement>> {
+        let start = self.next;
+        self.expect(":")?;
+        let double_colon = self.at(":");
+        if double_colon {
+            self.next += 1;
+        }
+        let name_start = self.pos();
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.to_ascii_lowercase(),
+            _ if double_colon => return Err(self.error("a pseudo-element")),
+            _ => {
+                self.next = start;
+                return Ok(None);
+            }
+        };
+        let pseudo_element = match &*name {
+            "before" => PseudoElement::Before,
+            "after" => PseudoElement::After,
+            _ if double_colon => {
+                return Err(self.error_at(name_start, "a pseudo-element", format!("{:?}", name)))
+            }
+            _ => {
+                self.next = start;
+                return Ok(None);
+            }
+        };
+        self.next += 1;
+        Ok(Some(pseudo_element))
     }
 
-    // Parse a single element, including its open tag, contents, and closing tag.
-    fn parse_element(&mut self) -> Node {
-        // Opening tag.
-        self.expect("<");
-        let tag_name = self.parse_name();
-        let attrs = self.parse_attributes();
-        self.expect(">");
+    // Parse a pseudo-class, e.g. `:first-child` or `:nth-child(2n+1 of .item)`.
+    fn parse_pseudo_class(&mut self) -> ParseResult<PseudoClass> {
+        self.expect(":")?;
+        let start = self.pos();
+        let (name, functional) = match self.peek() {
+            Some(Token::Ident(name)) => (name.to_ascii_lowercase(), false),
+            Some(Token::Function(name)) => (name.to_ascii_lowercase(), true),
+            _ => return Err(self.error("a pseudo-class")),
+        };
+        self.next += 1;
+        if functional {
+            self.consume_whitespace();
+        }
+        let pseudo_class = match (&*name, functional) {
+            ("root", false) => PseudoClass::Root,
+            ("empty", false) => PseudoClass::Empty,
+            ("first-child", false) => PseudoClass::FirstChild,
+            ("last-child", false) => PseudoClass::LastChild,
+            ("only-child", false) => PseudoClass::OnlyChild,
+            ("nth-child", true) => {
+                let nth = self.parse_nth()?;
+                self.consume_whitespace();
+                let of = match self.peek() {
+                    Some(Token::RightParen) => None,
+                    Some(Token::Ident(keyword)) if keyword.eq_ignore_ascii_case("of") => {
+                        self.next += 1;
+                        self.consume_whitespace();
+                        Some(self.parse_selector_arguments()?)
+                    }
+                    _ => return Err(self.error("\"of\" or ')'")),
+                };
+                PseudoClass::NthChild(nth, of)
+            }
+            ("nth-of-type", true) => PseudoClass::NthOfType(self.parse_nth()?),
+            ("not", true) => PseudoClass::Not(self.parse_selector_arguments()?),
+            ("is", true) => PseudoClass::Is(self.parse_selector_arguments()?),
+            ("where", true) => PseudoClass::Where(self.parse_selector_arguments()?),
+            ("has", true) => PseudoClass::Has(self.parse_relative_selectors()?),
+            _ => return Err(self.error_at(start, "a pseudo-class", format!("{:?}", name))),
+        };
+        if functional {
+            self.consume_whitespace();
+            self.expect(")")?;
+        }
+        Ok(pseudo_class)
+    }
 
-        // Contents.
-        let children = self.parse_nodes();
+    // Parse an `An+B` expression such as `odd`, `3`, `2n+1` or `-n + 3`, up to the closing `)`
+    // or an `of`. Whitespace is only allowed around the sign before B.
+    // https://www.w3.org/TR/css-syntax-3/#anb-microsyntax
+    fn parse_nth(&mut self) -> ParseResult<Nth> {
+        let (start, first) = (self.pos(), self.next);
+        let mut nth = self.parse_an_plus_b();
+        self.consume_whitespace();
+        if !self.at_nth_end() {
+            nth = None;
+        }
+        match nth {
+            Some(nth) => Ok(nth),
+            None => {
+                self.next = first;
+                while !self.at_nth_end() {
+                    self.next += 1;
+                }
+                let found = self.input[start..self.pos()].trim_end();
+                Err(self.error_at(start, "an+b", format!("{:?}", found)))
+            }
+        }
+    }
 
-        // Closing tag.
-        self.expect("</");
-        self.expect(&*tag_name.clone());
-        self.expect(">");
+    // Whether the next token ends the argument of `:nth-child()`.
+    fn at_nth_end(&self) -> bool {
+        match self.peek() {
+            None | Some(Token::RightParen) => true,
+            Some(Token::Ident(word)) => word.eq_ignore_ascii_case("of"),
+            _ => false,
+        }
+    }
 
-        elem(tag_name, attrs, children)
+    fn parse_an_plus_b(&mut self) -> Option<Nth> {
+        let index = self.next;
+        // Find A, and whatever follows the `n` in the same token: `2n-1` is a single dimension
+        // and `-n-1` a single identifier.
+        let (a, rest) = match self.consume()? {
+            Token::Ident(name) => match &*name.to_ascii_lowercase() {
+                "odd" => return Some(Nth { a: 2, b: 1 }),
+                "even" => return Some(Nth { a: 2, b: 0 }),
+                name => match name.strip_prefix('-') {
+                    Some(rest) => (-1, rest.to_string()),
+                    None => (1, name.to_string()),
+                },
+            },
+            // `+n`, with no whitespace after the `+`.
+            Token::Delim('+') => match self.consume()? {
+                Token::Ident(name) if !name.starts_with('-') => (1, name.to_ascii_lowercase()),
+                _ => return None,
+            },
+            Token::Number(number) if number.integer => {
+                return self.leading_integer(index).map(|b| Nth { a: 0, b })
+            }
+            Token::Dimension(number, unit) if number.integer => {
+                (self.leading_integer(index)?, unit.to_ascii_lowercase())
+            }
+            _ => return None,
+        };
+        let b = match rest.strip_prefix('n')? {
+            "" => {
+                let end_of_a = self.next;
+                self.consume_whitespace();
+                match self.peek().cloned() {
+                    // `2n +1`
+                    Some(Token::Number(number))
+                        if number.integer && self.source(self.next).starts_with(['+', '-']) =>
+                    {
+                        self.next += 1;
+                        self.leading_integer(self.next - 1)?
+                    }
+                    // `2n + 1`
+                    Some(Token::Delim(sign @ ('+' | '-'))) => {
+                        self.next += 1;
+                        self.consume_whitespace();
+                        self.signless_integer(sign)?
+                    }
+                    _ => {
+                        self.next = end_of_a;
+                        0
+                    }
+                }
+            }
+            // `2n- 1`
+            "-" => {
+                self.consume_whitespace();
+                self.signless_integer('-')?
+            }
+            // `2n-1`
+            digits if digits.len() > 1 && digits[1..].bytes().all(|c| c.is_ascii_digit()) => {
+                digits.parse().ok()?
+            }
+            _ => return None,
+        };
+        Some(Nth { a, b })
     }
 
-    // Parse a single name="value" pair.
-    fn parse_attr(&mut self) -> (String, String) {
-        let name = self.parse_name();
-        self.expect("=");
-        let value = self.parse_attr_value();
-        (name, value)
+    // The integer at the start of the token at `index`, e.g. `-2` in `-2n`, if it's in range.
+    fn leading_integer(&self, index: usize) -> Option<i32> {
+        let source = self.source(index);
+        let end = source
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && matches!(c, '+' | '-'))))
+            .map_or(source.len(), |(i, _)| i);
+        source[..end].parse().ok()
     }
 
-    // Parse a quoted value.
-    fn parse_attr_value(&mut self) -> String {
-        let open_quote = self.consume_char();
-        assert!(open_quote == '"' || open_quote == '\'');
-        let value = self.consume_while(|c| c != open_quote);
-        let close_quote = self.consume_char();
-        assert_eq!(open_quote, close_quote);
-        value
+    // Consume an integer written without a sign, and give it `sign`.
+    fn signless_integer(&mut self, sign: char) -> Option<i32> {
+        match self.peek() {
+            Some(Token::Number(number))
+                if number.integer
+                    && self
+                        .source(self.next)
+                        .starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                self.next += 1;
+                format!("{}{}", sign, self.source(self.next - 1))
+                    .parse()
+                    .ok()
+            }
+            _ => None,
+        }
     }
 
-    // Parse a list of name="value" pairs, separated by whitespace.
-    fn parse_attributes(&mut self) -> AttrMap {
-        let mut attrs = HashMap::new();
+    // Parse the comma-separated selectors that are the argument of a pseudo-class like `:is()`,
+    // up to the closing `)`.
+    fn parse_selector_arguments(&mut self) -> ParseResult<Vec<Selector>> {
+        let mut selectors = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '>' {
-                break;
+            selectors.push(self.parse_selector()?);
+            self.consume_whitespace();
+            match self.peek() {
+                Some(Token::Comma) => self.next += 1,
+                Some(Token::RightParen) => break,
+                _ => return Err(self.error("',' or ')' in selector list")),
             }
-            let (name, value) = self.parse_attr();
-            attrs.insert(name, value);
         }
-        AttrMap { attrs }
+        Ok(selectors)
     }
 
-    // Parse a sequence of sibling nodes.
-    fn parse_nodes(&mut self) -> Vec<Node> {
-        let mut nodes = Vec::new();
+    // Parse the argument of `:has()`: selectors that may start with a combinator, e.g. `> img`.
+    fn parse_relative_selectors(&mut self) -> ParseResult<Vec<RelativeSelector>> {
+        let mut selectors = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
-                break;
+            let combinator = match self.peek() {
+                Some(Token::Delim('>')) => Combinator::Child,
+                Some(Token::Delim('+')) => Combinator::NextSibling,
+                Some(Token::Delim('~')) => Combinator::SubsequentSibling,
+                _ => Combinator::Descendant,
+            };
+            if combinator != Combinator::Descendant {
+                self.next += 1;
+                self.consume_whitespace();
+            }
+            let selector = self.parse_selector()?;
+            selectors.push(RelativeSelector {
+                combinator,
+                selector,
+            });
+            self.consume_whitespace();
+            match self.peek() {
+                Some(Token::Comma) => self.next += 1,
+                Some(Token::RightParen) => break,
+                _ => return Err(self.error("',' or ')' in selector list")),
             }
-            nodes.push(self.parse_node());
         }
-        nodes
+        Ok(selectors)
     }
 
-    // Parse an HTML document and return the root element.
-    pub(crate) fn parse_html(&mut self) -> Node {
-        let mut nodes = self.parse_nodes();
-
-        // If the document contains a root element, just return it. Otherwise, create one.
-        if nodes.len() == 1 {
-            nodes.remove(0)
-        } else {
-            elem(
-                "html".to_string(),
-                AttrMap {
-                    attrs: HashMap::new(),
-                },
-                nodes,
-            )
+    fn parse_identifier(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(Token::Ident(identifier)) => {
+                let identifier = identifier.clone();
+                self.next += 1;
+                Ok(identifier)
+            }
+            _ => Err(self.error("an identifier")),
         }
     }
 
-    // Parse one simple selector, e.g.: `type#id.class1.class2.class3`
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
-        let mut selector = SimpleSelector {
-            tag_name: None,
-            id: None,
-            class: Vec::new(),
-        };
-        while !self.eof() {
-            match self.next_char() {
-                '#' => {
-                    self.consume_char();
-                    selector.id = Some(self.parse_identifier());
-                }
-                '.' => {
-                    self.consume_char();
-                    selector.class.push(self.parse_identifier());
+    // Parse a rule set: `<selectors> { <declarations> }`. If the selectors are invalid, the
+    // whole rule is skipped.
+    // https://www.w3.org/TR/css-syntax-3/#consume-qualified-rule
+    fn parse_rule(&mut self) -> Option<Rule> {
+        let selectors = match self.parse_selectors() {
+            Ok(selectors) => selectors,
+            Err(error) => {
+                self.diagnostics.push(error);
+                while let Some(token) = self.peek() {
+                    let block = *token == Token::LeftBrace;
+                    self.consume_component_value();
+                    if block {
+                        break;
+                    }
                 }
-                '*' => {
-                    // universal selector
-                    self.consume_char();
+                return None;
+            }
+        };
+        // `parse_selectors` stops at the `{`.
+        self.next += 1;
+        Some(Rule {
+            selectors,
+            declarations: self.parse_declaration_list(true),
+        })
+    }
+
+    // Skip an at-rule such as `@media` or `@import`, up to the end of its block or its `;`.
+    // https://www.w3.org/TR/css-syntax-3/#consume-at-rule
+    fn skip_at_rule(&mut self) {
+        self.next += 1;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Semicolon => {
+                    self.next += 1;
+                    return;
                 }
-                c if matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_') => {
-                    selector.tag_name = Some(self.parse_identifier());
+                Token::LeftBrace => {
+                    self.consume_component_value();
+                    return;
                 }
-                _ => break,
+                _ => self.consume_component_value(),
             }
         }
-        selector
-    }
-
-    fn parse_identifier(&mut self) -> String {
-        // TODO: Include U+00A0 and higher.
-        self.consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_'))
     }
 
-    // Parse a rule set: `<selectors> { <declarations> }`.
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    // Parse a selector: simple selectors joined by combinators, e.g. `ul > li.item a`.
+    fn parse_selector(&mut self) -> ParseResult<Selector> {
+        let mut selector = Selector::Simple(self.parse_simple_selector()?);
+        loop {
+            let whitespace = self.consume_whitespace();
+            let combinator = match self.peek() {
+                Some(Token::Delim('>')) => Combinator::Child,
+                Some(Token::Delim('+')) => Combinator::NextSibling,
+                Some(Token::Delim('~')) => Combinator::SubsequentSibling,
+                Some(Token::Comma | Token::LeftBrace | Token::RightParen) | None => break,
+                // Whitespace between two simple selectors is the descendant combinator.
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(_) => break,
+            };
+            if selector.pseudo_element().is_some() {
+                return Err(self.error("',' or '{' after a pseudo-element"));
+            }
+            if combinator != Combinator::Descendant {
+                self.next += 1;
+                self.consume_whitespace();
+            }
+            let right = self.parse_simple_selector()?;
+            selector = Selector::Complex(Box::new(selector), combinator, right);
         }
+        Ok(selector)
     }
 
     // Parse a comma-separated list of selectors.
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
+            selectors.push(self.parse_selector()?);
             self.consume_whitespace();
-            match self.next_char() {
-                ',' => {
-                    self.consume_char();
+            match self.peek() {
+                Some(Token::Comma) => {
+                    self.next += 1;
                     self.consume_whitespace();
                 }
-                '{' => break, // start of declarations
-                c => panic!("Unexpected character {} in selector list", c),
+                Some(Token::LeftBrace) => break, // start of declarations
+                _ => return Err(self.error("',' or '{' in selector list")),
             }
         }
         // Return selectors with highest specificity first, for use in matching.
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        selectors
+        selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+        Ok(selectors)
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
+    // Parse declarations separated by `;`, up to the `}` that ends the block if `block` is
+    // true, or the end of the input otherwise. Invalid declarations are skipped.
+    // https://www.w3.org/TR/css-syntax-3/#consume-list-of-declarations
+    fn parse_declaration_list(&mut self, block: bool) -> Vec<Declaration> {
         let mut declarations = Vec::new();
-        self.expect("{");
-        self.consume_whitespace();
-        while self.next_char() != '}' {
-            declarations.push(self.parse_declaration());
-            self.consume_whitespace();
+        loop {
+            match self.peek() {
+                Some(Token::Whitespace | Token::Semicolon) => self.next += 1,
+                Some(Token::RightBrace) if block => {
+                    self.next += 1;
+                    break;
+                }
+                Some(Token::AtKeyword(_)) => self.skip_at_rule(),
+                Some(_) => match self.parse_declaration() {
+                    Ok(declaration) => declarations.push(declaration),
+                    Err(error) => {
+                        self.diagnostics.push(error);
+                        self.skip_declaration(block);
+                    }
+                },
+                None => {
+                    // The end of the input closes any open block.
+                    if block {
+                        self.diagnostics.push(self.error("'}'"));
+                    }
+                    break;
+                }
+            }
         }
-        self.expect("}");
-        self.consume_whitespace();
         declarations
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
-        let name = self.consume_while(|c| c != ':');
-        self.expect(":");
+    // Skip the rest of an invalid declaration, up to and including its `;`. The `}` at the end
+    // of the block is left alone.
+    fn skip_declaration(&mut self, block: bool) {
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Semicolon => {
+                    self.next += 1;
+                    return;
+                }
+                Token::RightBrace if block => return,
+                _ => self.consume_component_value(),
+            }
+        }
+    }
+
+    fn parse_declaration(&mut self) -> ParseResult<Declaration> {
+        let name = match self.peek() {
+            // Custom properties are case-sensitive, other property names aren't.
+            Some(Token::Ident(name)) if name.starts_with("--") => name.clone(),
+            Some(Token::Ident(name)) => name.to_ascii_lowercase(),
+            _ => return Err(self.error("a property name")),
+        };
+        self.next += 1;
         self.consume_whitespace();
-        let value;
-        if self.next_char() == '#' {
-            value = Value::ColorValue(
-                Color::try_from(self.consume_while(|c| c != ';')).expect("Failed to parse color"),
-            );
-        } else if matches!(self.next_char(), '0'..='9') {
-            value = self.parse_length_value();
-        } else {
-            value = Value::Keyword(self.consume_while(|c| c != ';'))
+        self.expect(":")?;
+        self.consume_whitespace();
+        let value = self.parse_value(&name)?;
+        let important = self.parse_important()?;
+        // The `;` after the last declaration is optional.
+        match self.peek() {
+            Some(Token::Semicolon) => self.next += 1,
+            Some(Token::RightBrace) | None => {}
+            Some(_) => return Err(self.error("\";\"")),
+        }
+        Ok(Declaration {
+            name,
+            value,
+            important,
+        })
+    }
+
+    // Parse the value of property `name`, and any whitespace after it: a single component value,
+    // or a list of them separated by spaces or commas, like `10px 20px` or `Arial, sans-serif`.
+    fn parse_value(&mut self, name: &str) -> ParseResult<Value> {
+        if name == "content" && !matches!(self.peek(), Some(Token::Ident(_))) {
+            let value = self.parse_content()?;
+            self.consume_whitespace();
+            return Ok(value);
+        }
+        // Commas bind looser than spaces: `a b, c` is a comma-separated list of `a b` and `c`.
+        let mut comma_separated = Vec::new();
+        loop {
+            let mut space_separated = Vec::new();
+            while !self.at_end_of_value() && self.peek() != Some(&Token::Comma) {
+                space_separated.push(self.parse_component_value()?);
+                self.consume_whitespace();
+            }
+            if space_separated.is_empty() {
+                return Err(self.error("a value"));
+            }
+            comma_separated.push(Value::list(space_separated, Separator::Space));
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.next += 1;
+            self.consume_whitespace();
+        }
+        Ok(Value::list(comma_separated, Separator::Comma))
+    }
+
+    // Parse one item of a value, e.g. `#cc0000`, `10px`, `auto` or `"Times New Roman"`.
+    fn parse_component_value(&mut self) -> ParseResult<Value> {
+        let start = self.pos();
+        let value = match self.peek() {
+            Some(Token::Hash { value, .. }) => {
+                let text = format!("#{}", value);
+                Value::ColorValue(
+                    Color::try_from(text.clone())
+                        .map_err(|_| self.error_at(start, "a color", format!("{:?}", text)))?,
+                )
+            }
+            Some(Token::Dimension(number, unit)) if unit.eq_ignore_ascii_case("px") => {
+                Value::Length(number.value, Unit::Px)
+            }
+            Some(Token::Number(number)) => Value::Number(number.value),
+            // This is the only unit we support right now
+            Some(Token::Dimension(..) | Token::Percentage(_)) => return Err(self.error("\"px\"")),
+            Some(Token::Ident(keyword)) => Value::Keyword(keyword.clone()),
+            Some(Token::String(text)) => Value::String(text.clone()),
+            // None of our properties take these. A stray `:` usually means a missing `;` before
+            // the next declaration.
+            Some(
+                Token::Colon
+                | Token::BadString
+                | Token::BadUrl
+                | Token::RightParen
+                | Token::RightBracket,
+            ) => return Err(self.error("a value")),
+            // Anything else, like `rgb(0, 0, 0)` or the `/` in `font: 12px/16px serif`, is kept
+            // as written.
+            _ => {
+                let start = self.next;
+                self.consume_component_value();
+                let text: String = (start..self.next)
+                    .map(|i| match self.tokens[i].token {
+                        Token::Whitespace => " ",
+                        _ => self.source(i),
+                    })
+                    .collect();
+                return Ok(Value::Keyword(text));
+            }
+        };
+        self.next += 1;
+        Ok(value)
+    }
+
+    // Is the next token the end of a declaration's value?
+    fn at_end_of_value(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(Token::Semicolon | Token::RightBrace | Token::Delim('!'))
+        )
+    }
+
+    // Parse the parts of a `content` value: strings, `attr()`, `counter()` and `counters()`.
+    fn parse_content(&mut self) -> ParseResult<Value> {
+        let mut items = Vec::new();
+        while !self.at_end_of_value() {
+            let start = self.pos();
+            let item = match self.peek() {
+                Some(Token::String(text)) => {
+                    let item = ContentItem::String(text.clone());
+                    self.next += 1;
+                    item
+                }
+                Some(Token::Function(function)) => {
+                    let function = function.to_ascii_lowercase();
+                    if !matches!(&*function, "attr" | "counter" | "counters") {
+                        return Err(self.error_at(
+                            start,
+                            "a string, attr() or counter()",
+                            format!("{:?}", function),
+                        ));
+                    }
+                    self.next += 1;
+                    self.consume_whitespace();
+                    let name = self.parse_identifier()?;
+                    self.consume_whitespace();
+                    let item = match &*function {
+                        "attr" => ContentItem::Attr(name.to_ascii_lowercase()),
+                        "counter" => ContentItem::Counter(name, self.parse_counter_style()?),
+                        _ => {
+                            self.expect(",")?;
+                            self.consume_whitespace();
+                            let separator = match self.peek() {
+                                Some(Token::String(separator)) => separator.clone(),
+                                _ => return Err(self.error("a string")),
+                            };
+                            self.next += 1;
+                            self.consume_whitespace();
+                            ContentItem::Counters(name, separator, self.parse_counter_style()?)
+                        }
+                    };
+                    self.expect(")")?;
+                    item
+                }
+                _ => return Err(self.error("a string, attr() or counter()")),
+            };
+            items.push(item);
+            self.consume_whitespace();
         }
-        self.expect(";");
-        Declaration { name, value }
+        Ok(Value::Content(items))
     }
 
-    fn parse_length_value(&mut self) -> Value {
-        let num = self
-            .consume_while(|c| matches!(c, '0'..='9' | '.'))
-            .parse::<f32>()
-            .expect("Failed to parse length value");
-        let unit = self.parse_length_unit();
-        Value::Length(num, unit)
+    // Parse the optional `, <style>` at the end of `counter()` and `counters()`.
+    fn parse_counter_style(&mut self) -> ParseResult<String> {
+        if self.peek() != Some(&Token::Comma) {
+            return Ok("decimal".to_string());
+        }
+        self.next += 1;
+        self.consume_whitespace();
+        let style = self.parse_identifier()?.to_ascii_lowercase();
+        self.consume_whitespace();
+        Ok(style)
+    }
+
+    // Parse an optional `!important` after a declaration's value.
+    fn parse_important(&mut self) -> ParseResult<bool> {
+        if self.peek() != Some(&Token::Delim('!')) {
+            return Ok(false);
+        }
+        self.next += 1;
+        self.consume_whitespace();
+        match self.peek() {
+            Some(Token::Ident(keyword)) if keyword.eq_ignore_ascii_case("important") => {
+                self.next += 1;
+                self.consume_whitespace();
+                Ok(true)
+            }
+            _ => Err(self.error("\"important\"")),
+        }
     }
 
-    fn parse_length_unit(&mut self) -> Unit {
-        // This is the only unit we support right now
-        self.expect("px");
-        Unit::Px
+    // Parse the value of a `style` attribute: declarations without the surrounding braces.
+    pub(crate) fn parse_style_attribute(&mut self) -> Vec<Declaration> {
+        self.tokens = tokenize(&self.input);
+        self.parse_declaration_list(false)
     }
 
+    // Parse a stylesheet. We don't support any at-rules, so they are skipped. Errors are
+    // collected in `diagnostics`.
+    // https://www.w3.org/TR/css-syntax-3/#consume-list-of-rules
     pub(crate) fn parse_css(&mut self) -> Stylesheet {
+        self.tokens = tokenize(&self.input);
         let mut rules = Vec::new();
-        while !self.eof() {
-            rules.push(self.parse_rule())
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Whitespace | Token::Cdo | Token::Cdc => self.next += 1,
+                Token::AtKeyword(_) => self.skip_at_rule(),
+                _ => rules.extend(self.parse_rule()),
+            }
+        }
+        Stylesheet {
+            rules,
+            origin: Origin::Author,
         }
-        Stylesheet { rules }
     }
 }
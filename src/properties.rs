@@ -0,0 +1,91 @@
+// The CSS properties we know about: whether each one is inherited, and its initial value.
+//
+// https://www.w3.org/TR/css-cascade-4/#inheriting
+// https://www.w3.org/TR/css-cascade-4/#initial-values
+
+use crate::css::{Color, Unit, Value};
+
+pub(crate) struct Property {
+    pub(crate) name: &'static str,
+    pub(crate) inherited: bool,
+    initial: Initial,
+}
+
+// An initial value. `Value` owns its strings, so it can't be built in a constant.
+enum Initial {
+    Keyword(&'static str),
+    Px(f32),
+    Color(Color),
+}
+
+const BLACK: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 255,
+};
+
+const fn property(name: &'static str, inherited: bool, initial: Initial) -> Property {
+    Property {
+        name,
+        inherited,
+        initial,
+    }
+}
+
+const PROPERTIES: &[Property] = &[
+    property("background", false, Initial::Keyword("transparent")),
+    property("background-color", false, Initial::Keyword("transparent")),
+    property("border-bottom-width", false, Initial::Px(0.0)),
+    property("border-color", false, Initial::Keyword("currentcolor")),
+    property("border-left-width", false, Initial::Px(0.0)),
+    property("border-right-width", false, Initial::Px(0.0)),
+    property("border-top-width", false, Initial::Px(0.0)),
+    property("border-width", false, Initial::Px(0.0)),
+    property("color", true, Initial::Color(BLACK)),
+    property("content", false, Initial::Keyword("normal")),
+    property("counter-increment", false, Initial::Keyword("none")),
+    property("counter-reset", false, Initial::Keyword("none")),
+    property("display", false, Initial::Keyword("inline")),
+    property("font-family", true, Initial::Keyword("serif")),
+    property("font-size", true, Initial::Px(16.0)),
+    property("font-style", true, Initial::Keyword("normal")),
+    property("font-weight", true, Initial::Keyword("normal")),
+    property("height", false, Initial::Keyword("auto")),
+    property("line-height", true, Initial::Keyword("normal")),
+    property("list-style-type", true, Initial::Keyword("disc")),
+    property("margin", false, Initial::Px(0.0)),
+    property("margin-bottom", false, Initial::Px(0.0)),
+    property("margin-left", false, Initial::Px(0.0)),
+    property("margin-right", false, Initial::Px(0.0)),
+    property("margin-top", false, Initial::Px(0.0)),
+    property("padding", false, Initial::Px(0.0)),
+    property("padding-bottom", false, Initial::Px(0.0)),
+    property("padding-left", false, Initial::Px(0.0)),
+    property("padding-right", false, Initial::Px(0.0)),
+    property("padding-top", false, Initial::Px(0.0)),
+    property("text-align", true, Initial::Keyword("start")),
+    property("visibility", true, Initial::Keyword("visible")),
+    property("white-space", true, Initial::Keyword("normal")),
+    property("width", false, Initial::Keyword("auto")),
+];
+
+impl Property {
+    pub(crate) fn initial_value(&self) -> Value {
+        match self.initial {
+            Initial::Keyword(keyword) => Value::Keyword(keyword.to_string()),
+            Initial::Px(px) => Value::Length(px, Unit::Px),
+            Initial::Color(color) => Value::ColorValue(color),
+        }
+    }
+}
+
+// Look up a property by name, or `None` if we don't know about it.
+pub(crate) fn lookup(name: &str) -> Option<&'static Property> {
+    PROPERTIES.iter().find(|property| property.name == name)
+}
+
+// All properties that inherit by default.
+pub(crate) fn inherited() -> impl Iterator<Item = &'static Property> {
+    PROPERTIES.iter().filter(|property| property.inherited)
+}
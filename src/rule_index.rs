@@ -0,0 +1,99 @@
+// The selectors of a stylesheet, bucketed by the id, class or tag name of their rightmost simple
+// selector, so that styling an element only looks at rules that could possibly match it.
+
+use crate::ancestor_filter::selector_hashes;
+use crate::css::{Origin, Rule, Selector, Specificity, Stylesheet};
+use crate::html::ElementData;
+use std::collections::HashMap;
+
+pub(crate) struct RuleIndex<'a> {
+    pub(crate) origin: Origin,
+    by_id: HashMap<&'a str, Vec<IndexedSelector<'a>>>,
+    by_class: HashMap<&'a str, Vec<IndexedSelector<'a>>>,
+    by_tag: HashMap<&'a str, Vec<IndexedSelector<'a>>>,
+    // Selectors with no id, class or tag name on the right, like `*` or `[lang]`.
+    universal: Vec<IndexedSelector<'a>>,
+}
+
+pub(crate) struct IndexedSelector<'a> {
+    pub(crate) selector: &'a Selector,
+    pub(crate) specificity: Specificity,
+    pub(crate) rule: &'a Rule,
+    // The position of the rule in the stylesheet.
+    pub(crate) position: usize,
+    // See `AncestorFilter`.
+    pub(crate) ancestor_hashes: Vec<u32>,
+}
+
+impl<'a> RuleIndex<'a> {
+    pub(crate) fn new(stylesheet: &'a Stylesheet) -> Self {
+        Self::build(stylesheet, true)
+    }
+
+    // An "index" with every selector in the universal bucket and no ancestor hashes, so that
+    // every rule is tried against every element. Only for comparison in benchmarks.
+    #[cfg(test)]
+    pub(crate) fn unindexed(stylesheet: &'a Stylesheet) -> Self {
+        Self::build(stylesheet, false)
+    }
+
+    fn build(stylesheet: &'a Stylesheet, use_index: bool) -> Self {
+        let mut index = RuleIndex {
+            origin: stylesheet.origin,
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: Vec::new(),
+        };
+        for (position, rule) in stylesheet.rules.iter().enumerate() {
+            for selector in &rule.selectors {
+                let indexed = IndexedSelector {
+                    selector,
+                    specificity: selector.specificity(),
+                    rule,
+                    position,
+                    ancestor_hashes: if use_index {
+                        selector_hashes(selector)
+                    } else {
+                        Vec::new()
+                    },
+                };
+                let rightmost = selector.rightmost();
+                // An element can only match if it has all of these, so any one will do as the
+                // key. Ids are the most selective.
+                let bucket = if !use_index {
+                    &mut index.universal
+                } else if let Some(id) = &rightmost.id {
+                    index.by_id.entry(id).or_default()
+                } else if let Some(class) = rightmost.class.first() {
+                    index.by_class.entry(class).or_default()
+                } else if let Some(tag_name) = &rightmost.tag_name {
+                    index.by_tag.entry(tag_name).or_default()
+                } else {
+                    &mut index.universal
+                };
+                bucket.push(indexed);
+            }
+        }
+        index
+    }
+
+    // The selectors that might match an element. Each one comes up at most once.
+    pub(crate) fn candidates<'b>(
+        &'b self,
+        element: &'b ElementData,
+    ) -> impl Iterator<Item = &'b IndexedSelector<'a>> {
+        let by_id = element.id().and_then(|id| self.by_id.get(id.as_str()));
+        let by_class = element
+            .classes()
+            .into_iter()
+            .filter_map(|class| self.by_class.get(class));
+        let by_tag = self.by_tag.get(element.tag_name.as_str());
+        by_id
+            .into_iter()
+            .chain(by_class)
+            .chain(by_tag)
+            .flatten()
+            .chain(&self.universal)
+    }
+}
@@ -0,0 +1,932 @@
+use crate::ancestor_filter::AncestorFilter;
+use crate::css::{
+    AttributeOperator, AttributeSelector, Combinator, ContentItem, Declaration, Origin,
+    PseudoClass, PseudoElement, RelativeSelector, Rule, Selector, SimpleSelector, Specificity,
+    Stylesheet, Unit, Value,
+};
+use crate::html::{ElementData, Node, NodeType};
+use crate::parser::Parser;
+use crate::properties;
+use crate::rule_index::RuleIndex;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+// Map from CSS property names to values.
+pub(crate) type PropertyMap = HashMap<String, Value>;
+
+// A node with associated style data.
+pub(crate) struct StyledNode<'a> {
+    pub(crate) node: &'a Node,
+    // Set for nodes that aren't in the DOM, in which case `node` is the element they belong to.
+    pub(crate) generated: Option<Generated>,
+    pub(crate) specified_values: PropertyMap,
+    pub(crate) children: Vec<StyledNode<'a>>,
+}
+
+// A styled node created by CSS rather than the document.
+pub(crate) enum Generated {
+    // The box of a `::before` or `::after` pseudo-element.
+    PseudoElement(PseudoElement),
+    // Text from the `content` property, inside a pseudo-element's box.
+    Text(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Display {
+    Inline,
+    Block,
+    None,
+}
+
+impl StyledNode<'_> {
+    // Return the specified value of a property if it exists, otherwise `None`.
+    pub(crate) fn value(&self, name: &str) -> Option<Value> {
+        self.specified_values.get(name).cloned()
+    }
+
+    // Return the specified value of property `name`, or property `fallback_name` if that doesn't
+    // exist, or value `default` if neither does.
+    pub(crate) fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
+        self.value(name)
+            .unwrap_or_else(|| self.value(fallback_name).unwrap_or_else(|| default.clone()))
+    }
+
+    // The value of the `display` property (defaults to inline).
+    pub(crate) fn display(&self) -> Display {
+        match self.value("display") {
+            Some(Value::Keyword(s)) => match &*s {
+                "block" => Display::Block,
+                "none" => Display::None,
+                _ => Display::Inline,
+            },
+            _ => Display::Inline,
+        }
+    }
+
+    // The text of a text node, whether from the DOM or generated.
+    pub(crate) fn text(&self) -> Option<&str> {
+        match (&self.generated, &self.node.node_type) {
+            (Some(Generated::Text(text)), _) | (None, NodeType::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn summary(&self) -> String {
+        match &self.generated {
+            None => self.node.summary(),
+            Some(Generated::PseudoElement(pseudo_element)) => {
+                format!("{:?}({})", pseudo_element, self.node.summary())
+            }
+            Some(Generated::Text(text)) => format!("Text({:?})", text),
+        }
+    }
+
+    fn pretty_print(&self, prefix: String, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut values: Vec<_> = self.specified_values.iter().collect();
+        values.sort_by(|a, b| a.0.cmp(b.0));
+        writeln!(f, "{}├──{:?} {:?}", prefix, self.summary(), values)?;
+        for child in self.children.iter() {
+            child.pretty_print(format!("{}   ", prefix), f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for StyledNode<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.pretty_print("".to_string(), f)
+    }
+}
+
+// The built-in user-agent stylesheet, which gives HTML elements their default rendering.
+pub(crate) const USER_AGENT_STYLESHEET: &str = include_str!("ua.css");
+
+// Shorthand properties, and the longhands they set.
+const SHORTHANDS: &[(&str, &[&str])] = &[
+    (
+        "margin",
+        &["margin-top", "margin-right", "margin-bottom", "margin-left"],
+    ),
+    (
+        "padding",
+        &[
+            "padding-top",
+            "padding-right",
+            "padding-bottom",
+            "padding-left",
+        ],
+    ),
+    (
+        "border-width",
+        &[
+            "border-top-width",
+            "border-right-width",
+            "border-bottom-width",
+            "border-left-width",
+        ],
+    ),
+    (
+        "border",
+        &[
+            "border-top-width",
+            "border-right-width",
+            "border-bottom-width",
+            "border-left-width",
+            "border-color",
+        ],
+    ),
+    ("background", &["background-color"]),
+];
+
+// Where a declaration ranks in the cascade. Declarations are applied in increasing order, so the
+// highest-ranked one is applied last and wins.
+// https://www.w3.org/TR/css-cascade-4/#cascade-sort
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct CascadePriority {
+    // Origin and importance, see `cascade_level`.
+    level: u8,
+    // Declarations from the `style` attribute beat any selector.
+    inline: bool,
+    specificity: Specificity,
+    // Position in the stylesheets, taken in the order they were given.
+    order: usize,
+}
+
+// Normal declarations rank by origin, user-agent lowest. `!important` declarations rank above
+// all normal ones, in the reverse order of their origins.
+fn cascade_level(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, Origin::UserAgent) => 0,
+        (false, Origin::User) => 1,
+        (false, Origin::Author) => 2,
+        (true, Origin::Author) => 3,
+        (true, Origin::User) => 4,
+        (true, Origin::UserAgent) => 5,
+    }
+}
+
+// Apply stylesheets to an entire DOM tree, returning a StyledNode tree. Where two declarations
+// are otherwise equal in the cascade, the one from the later stylesheet wins.
+pub(crate) fn style_tree<'a>(root: &'a Node, stylesheets: &[Stylesheet]) -> StyledNode<'a> {
+    style_tree_with(root, stylesheets.iter().map(RuleIndex::new).collect())
+}
+
+// Like `style_tree`, but trying every rule against every element. Only for comparison in
+// benchmarks.
+#[cfg(test)]
+pub(crate) fn style_tree_unindexed<'a>(
+    root: &'a Node,
+    stylesheets: &[Stylesheet],
+) -> StyledNode<'a> {
+    style_tree_with(root, stylesheets.iter().map(RuleIndex::unindexed).collect())
+}
+
+fn style_tree_with<'a>(root: &'a Node, indexes: Vec<RuleIndex>) -> StyledNode<'a> {
+    let mut context = StyleContext {
+        indexes,
+        ancestors: AncestorFilter::new(),
+        counters: Counters::default(),
+    };
+    style_node(root, None, 0, &mut context, &HashMap::new())
+}
+
+// What styling needs besides the node being styled.
+struct StyleContext<'s> {
+    indexes: Vec<RuleIndex<'s>>,
+    // The ancestors of the element being styled.
+    ancestors: AncestorFilter,
+    counters: Counters,
+}
+
+// Style `node`, the child at `index` of element `parent`.
+fn style_node<'a>(
+    node: &'a Node,
+    parent: Option<&ElementRef>,
+    index: usize,
+    context: &mut StyleContext,
+    parent_values: &PropertyMap,
+) -> StyledNode<'a> {
+    let element = match &node.node_type {
+        NodeType::Element(data) => Some(ElementRef {
+            node,
+            data,
+            parent,
+            index,
+        }),
+        _ => None,
+    };
+    let mut values = match &element {
+        Some(element) => specified_values(element, None, context),
+        None => HashMap::new(),
+    };
+    inherit(&mut values, parent_values);
+    context.counters.update(&values);
+
+    // `::before` and `::after` are styled as the first and last children of the element.
+    context.counters.enter();
+    let mut children = Vec::new();
+    let generate = |pseudo_element, context: &mut StyleContext| {
+        element.as_ref().and_then(|element| {
+            style_pseudo_element(node, element, pseudo_element, context, &values)
+        })
+    };
+    children.extend(generate(PseudoElement::Before, context));
+    if let Some(element) = &element {
+        context.ancestors.push(element.data);
+    }
+    for (i, child) in node.children.iter().enumerate() {
+        // Comments and DOCTYPEs are not rendered.
+        if matches!(child.node_type, NodeType::Element(_) | NodeType::Text(_)) {
+            let child = style_node(child, element.as_ref(), i, context, &values);
+            children.push(child);
+        }
+    }
+    if let Some(element) = &element {
+        context.ancestors.pop(element.data);
+    }
+    children.extend(generate(PseudoElement::After, context));
+    context.counters.leave();
+
+    StyledNode {
+        node,
+        generated: None,
+        children,
+        specified_values: values,
+    }
+}
+
+// Style a `::before` or `::after` pseudo-element of `element`, whose node is `node`. It only has
+// a box if its `content` property says what to put in it.
+fn style_pseudo_element<'a>(
+    node: &'a Node,
+    element: &ElementRef,
+    pseudo_element: PseudoElement,
+    context: &mut StyleContext,
+    element_values: &PropertyMap,
+) -> Option<StyledNode<'a>> {
+    let mut values = specified_values(element, Some(pseudo_element), context);
+    inherit(&mut values, element_values);
+    let Some(Value::Content(items)) = values.get("content") else {
+        return None;
+    };
+    let items = items.clone();
+    let counters = &mut context.counters;
+    counters.update(&values);
+    let text: String = items
+        .iter()
+        .map(|item| match item {
+            ContentItem::String(text) => text.clone(),
+            ContentItem::Attr(name) => element
+                .data
+                .attrs
+                .attrs
+                .get(name)
+                .cloned()
+                .unwrap_or_default(),
+            ContentItem::Counter(name, style) => format_counter(counters.value(name), style),
+            ContentItem::Counters(name, separator, style) => {
+                let values: Vec<_> = counters
+                    .values(name)
+                    .map(|value| format_counter(value, style))
+                    .collect();
+                match values.is_empty() {
+                    true => format_counter(0, style),
+                    false => values.join(separator),
+                }
+            }
+        })
+        .collect();
+
+    let mut children = Vec::new();
+    if !text.is_empty() {
+        let mut text_values = HashMap::new();
+        inherit(&mut text_values, &values);
+        children.push(StyledNode {
+            node,
+            generated: Some(Generated::Text(text)),
+            specified_values: text_values,
+            children: Vec::new(),
+        });
+    }
+    Some(StyledNode {
+        node,
+        generated: Some(Generated::PseudoElement(pseudo_element)),
+        specified_values: values,
+        children,
+    })
+}
+
+// The CSS counters in scope at the element being styled, innermost last.
+// https://www.w3.org/TR/css-lists-3/#auto-numbering
+#[derive(Default)]
+struct Counters {
+    // Each counter's name and value, and the depth of the element that created it.
+    counters: Vec<(String, i32, usize)>,
+    // The depth of the element being styled.
+    depth: usize,
+}
+
+impl Counters {
+    // Apply an element's `counter-reset` and then its `counter-increment`. Elements that aren't
+    // displayed don't affect counters.
+    fn update(&mut self, values: &PropertyMap) {
+        if matches!(values.get("display"), Some(Value::Keyword(display)) if display == "none") {
+            return;
+        }
+        for (name, value) in counter_changes(values.get("counter-reset"), 0) {
+            self.reset(name, value);
+        }
+        for (name, value) in counter_changes(values.get("counter-increment"), 1) {
+            match self.counters.iter_mut().rev().find(|(n, _, _)| n == name) {
+                // Counters stop at the limits of an i32 rather than wrapping around.
+                Some((_, counter, _)) => *counter = counter.saturating_add(value),
+                // Incrementing a counter that isn't in scope creates it.
+                None => self.reset(name, value),
+            }
+        }
+    }
+
+    // Create a counter. A counter is in scope for the element that creates it, its descendants,
+    // and its following siblings and their descendants, so a sibling creating another counter
+    // with the same name replaces it rather than nesting inside it.
+    fn reset(&mut self, name: &str, value: i32) {
+        let depth = self.depth;
+        let sibling_counter = self
+            .counters
+            .iter_mut()
+            .rev()
+            .take_while(|(_, _, d)| *d == depth)
+            .find(|(n, _, _)| n == name);
+        match sibling_counter {
+            Some((_, counter, _)) => *counter = value,
+            None => self.counters.push((name.to_string(), value, depth)),
+        }
+    }
+
+    // The value of the innermost counter with this name, or zero if there isn't one.
+    fn value(&self, name: &str) -> i32 {
+        self.values(name).last().unwrap_or(0)
+    }
+
+    // The values of all counters with this name, outermost first.
+    fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = i32> + 'a {
+        self.counters
+            .iter()
+            .filter(move |(n, _, _)| n == name)
+            .map(|(_, value, _)| *value)
+    }
+
+    // Move down to an element's children.
+    fn enter(&mut self) {
+        self.depth += 1;
+    }
+
+    // Move back up from an element's children. Counters they created go out of scope.
+    fn leave(&mut self) {
+        let depth = self.depth;
+        self.counters.retain(|(_, _, d)| *d < depth);
+        self.depth -= 1;
+    }
+}
+
+// Parse a `counter-reset` or `counter-increment` value: counter names, each optionally followed
+// by an integer which otherwise defaults to `default`.
+fn counter_changes(value: Option<&Value>, default: i32) -> Vec<(&str, i32)> {
+    let Some(value) = value else {
+        return Vec::new();
+    };
+    let mut changes: Vec<(&str, i32)> = Vec::new();
+    for item in value.space_separated() {
+        match (item, changes.last_mut()) {
+            // Numbers beyond the range of an i32 are clamped to it.
+            (Value::Number(number), Some((_, value))) if number.fract() == 0.0 => {
+                *value = number.clamp(i32::MIN as f32, i32::MAX as f32) as i32
+            }
+            (Value::Keyword(word), _) if word == "none" => {}
+            (Value::Keyword(word), _) => changes.push((word, default)),
+            _ => return Vec::new(),
+        }
+    }
+    changes
+}
+
+// Format a counter's value in a counter style. Styles that can't represent the value, and styles
+// we don't know, fall back to decimal.
+// https://www.w3.org/TR/css-counter-styles-3/#predefined-counters
+fn format_counter(value: i32, style: &str) -> String {
+    match style {
+        "none" => String::new(),
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25e6}".to_string(),
+        "square" => "\u{25aa}".to_string(),
+        "lower-alpha" | "lower-latin" if value > 0 => alphabetic(value),
+        "upper-alpha" | "upper-latin" if value > 0 => alphabetic(value).to_ascii_uppercase(),
+        "lower-roman" if (1..4000).contains(&value) => roman(value),
+        "upper-roman" if (1..4000).contains(&value) => roman(value).to_ascii_uppercase(),
+        _ => value.to_string(),
+    }
+}
+
+// 1 is "a", 26 is "z", 27 is "aa".
+fn alphabetic(mut value: i32) -> String {
+    let mut letters = Vec::new();
+    while value > 0 {
+        value -= 1;
+        letters.push((b'a' + (value % 26) as u8) as char);
+        value /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn roman(mut value: i32) -> String {
+    const NUMERALS: &[(i32, &str)] = &[
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut numeral = String::new();
+    for &(n, letters) in NUMERALS {
+        while value >= n {
+            numeral += letters;
+            value -= n;
+        }
+    }
+    numeral
+}
+
+// An element and its place in the tree, so that selectors can look at its ancestors and
+// siblings.
+struct ElementRef<'a> {
+    node: &'a Node,
+    data: &'a ElementData,
+    parent: Option<&'a ElementRef<'a>>,
+    // The position of `node` among its parent's children.
+    index: usize,
+}
+
+impl<'a> ElementRef<'a> {
+    fn ancestors(&self) -> impl Iterator<Item = &'a ElementRef<'a>> {
+        std::iter::successors(self.parent, |element| element.parent)
+    }
+
+    // The element children of this element.
+    fn children<'b>(&'b self) -> impl Iterator<Item = ElementRef<'b>> {
+        self.node
+            .children
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, node)| match &node.node_type {
+                NodeType::Element(data) => Some(ElementRef {
+                    node,
+                    data,
+                    parent: Some(self),
+                    index,
+                }),
+                _ => None,
+            })
+    }
+
+    // The elements after this one with the same parent, nearest first.
+    fn next_siblings(&self) -> impl Iterator<Item = ElementRef<'a>> {
+        let parent = self.parent;
+        let siblings = match parent {
+            Some(parent) => &parent.node.children[self.index + 1..],
+            None => &[],
+        };
+        let start = self.index + 1;
+        siblings
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, node)| match &node.node_type {
+                NodeType::Element(data) => Some(ElementRef {
+                    node,
+                    data,
+                    parent,
+                    index: start + i,
+                }),
+                _ => None,
+            })
+    }
+
+    // The elements before this one with the same parent, nearest first.
+    fn previous_siblings(&self) -> impl Iterator<Item = ElementRef<'a>> {
+        let parent = self.parent;
+        let siblings = match parent {
+            Some(parent) => &parent.node.children[..self.index],
+            None => &[],
+        };
+        siblings
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(move |(index, node)| match &node.node_type {
+                NodeType::Element(data) => Some(ElementRef {
+                    node,
+                    data,
+                    parent,
+                    index,
+                }),
+                _ => None,
+            })
+    }
+}
+
+// Fill in the values a node takes from its parent: inherited properties it doesn't set itself,
+// and any property set to `inherit`. `initial` and `unset` are resolved here too.
+// https://www.w3.org/TR/css-cascade-4/#defaulting-keywords
+fn inherit(values: &mut PropertyMap, parent_values: &PropertyMap) {
+    let defaulted: Vec<(String, String)> = values
+        .iter()
+        .filter_map(|(name, value)| match value {
+            Value::Keyword(keyword) if is_css_wide_keyword(value) => {
+                Some((name.clone(), keyword.to_ascii_lowercase()))
+            }
+            _ => None,
+        })
+        .collect();
+    for (name, keyword) in defaulted {
+        let property = properties::lookup(&name);
+        let inherits = match &*keyword {
+            "inherit" => true,
+            "unset" => property.is_some_and(|property| property.inherited),
+            _ => false,
+        };
+        // The parent doesn't store values it didn't set or inherit; those are initial values.
+        let value = match parent_value(parent_values, &name) {
+            Some(value) if inherits => Some(value.clone()),
+            _ => property.map(|property| property.initial_value()),
+        };
+        match value {
+            Some(value) => values.insert(name, value),
+            // We don't know the initial value of this property, so leave it unset.
+            None => values.remove(&name),
+        };
+    }
+
+    for property in properties::inherited() {
+        if !values.contains_key(property.name) {
+            if let Some(value) = parent_values.get(property.name) {
+                values.insert(property.name.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+fn is_css_wide_keyword(value: &Value) -> bool {
+    matches!(value, Value::Keyword(keyword) if ["inherit", "initial", "unset"]
+        .iter()
+        .any(|k| keyword.eq_ignore_ascii_case(k)))
+}
+
+// The parent's value of a property. A margin, padding or border width set with a single value
+// is only stored on the shorthand; see `longhand_values`.
+fn parent_value<'p>(parent_values: &'p PropertyMap, name: &str) -> Option<&'p Value> {
+    parent_values.get(name).or_else(|| {
+        let (shorthand, _) = SHORTHANDS
+            .iter()
+            .find(|(_, longhands)| longhands.contains(&name))?;
+        match *shorthand {
+            "margin" | "padding" | "border-width" => parent_values.get(*shorthand),
+            _ => None,
+        }
+    })
+}
+
+// Apply styles to a single element, or one of its pseudo-elements, returning the cascaded values.
+fn specified_values(
+    elem: &ElementRef,
+    pseudo_element: Option<PseudoElement>,
+    context: &StyleContext,
+) -> PropertyMap {
+    let mut declarations: Vec<(CascadePriority, &Declaration)> = Vec::new();
+    for index in &context.indexes {
+        for (specificity, rule) in matching_rules(elem, pseudo_element, index, &context.ancestors) {
+            for declaration in &rule.declarations {
+                let priority = CascadePriority {
+                    level: cascade_level(index.origin, declaration.important),
+                    inline: false,
+                    specificity,
+                    order: declarations.len(),
+                };
+                declarations.push((priority, declaration));
+            }
+        }
+    }
+
+    // The `style` attribute belongs to the document, like an author stylesheet. Invalid
+    // declarations in it are dropped. It doesn't apply to pseudo-elements.
+    let inline_declarations = match elem.data.attrs.attrs.get("style") {
+        Some(style) if pseudo_element.is_none() => {
+            Parser::new(style.clone()).parse_style_attribute()
+        }
+        _ => Vec::new(),
+    };
+    for declaration in &inline_declarations {
+        let priority = CascadePriority {
+            level: cascade_level(Origin::Author, declaration.important),
+            inline: true,
+            specificity: (0, 0, 0),
+            order: declarations.len(),
+        };
+        declarations.push((priority, declaration));
+    }
+
+    declarations.sort_by_key(|&(priority, _)| priority);
+    let mut values = HashMap::new();
+    for (_, declaration) in declarations {
+        set_value(&mut values, &declaration.name, declaration.value.clone());
+    }
+    values
+}
+
+// Set a property. A shorthand also overrides any of its longhands set earlier, so that e.g.
+// `margin` beats a `margin-top` from a lower-priority rule.
+fn set_value(values: &mut PropertyMap, name: &str, value: Value) {
+    if let Some((_, longhands)) = SHORTHANDS.iter().find(|(shorthand, _)| *shorthand == name) {
+        for longhand in *longhands {
+            values.remove(*longhand);
+        }
+        for (longhand, value) in longhand_values(name, longhands, &value) {
+            values.insert(longhand.to_string(), value);
+        }
+    }
+    values.insert(name.to_string(), value);
+}
+
+// The longhands set by a shorthand whose value is made of parts, like `margin: 10px 20px` or
+// `border: 1px solid #000`. A margin, padding or border width with a single value sets none of
+// them here; `StyledNode::lookup` falls back to the shorthand instead.
+// https://www.w3.org/TR/css-box-4/#margin-shorthand
+// https://www.w3.org/TR/css-backgrounds-3/#border-shorthands
+fn longhand_values(
+    name: &str,
+    longhands: &[&'static str],
+    value: &Value,
+) -> Vec<(&'static str, Value)> {
+    // `inherit`, `initial` and `unset` apply to each longhand.
+    if is_css_wide_keyword(value) {
+        return longhands
+            .iter()
+            .map(|longhand| (*longhand, value.clone()))
+            .collect();
+    }
+    let items = value.space_separated();
+    match name {
+        // Top, right, bottom and left. A missing bottom is the same as the top, and a missing
+        // left the same as the right.
+        "margin" | "padding" | "border-width" => {
+            let sides = match items {
+                [top, right] => [top, right, top, right],
+                [top, right, bottom] => [top, right, bottom, right],
+                [top, right, bottom, left] => [top, right, bottom, left],
+                _ => return Vec::new(),
+            };
+            longhands
+                .iter()
+                .copied()
+                .zip(sides.into_iter().cloned())
+                .collect()
+        }
+        // The parts can come in any order, and are told apart by their type. We don't draw
+        // border styles, but a border with style `none` has no width.
+        "border" => {
+            let (widths, color) = longhands.split_at(4);
+            let mut values = Vec::new();
+            for item in items {
+                let width = match item {
+                    Value::Length(..) | Value::Number(_) => item.clone(),
+                    Value::Keyword(style) if style == "none" || style == "hidden" => {
+                        Value::Length(0.0, Unit::Px)
+                    }
+                    Value::ColorValue(_) => {
+                        values.push((color[0], item.clone()));
+                        continue;
+                    }
+                    _ => continue,
+                };
+                values.extend(widths.iter().map(|width_name| (*width_name, width.clone())));
+            }
+            values
+        }
+        "background" => items
+            .iter()
+            .filter(|item| matches!(item, Value::ColorValue(_)))
+            .map(|color| (longhands[0], color.clone()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// A single CSS rule and the specificity of its most specific matching selector.
+type MatchedRule<'a> = (Specificity, &'a Rule);
+
+// Find all CSS rules that match the given element, or one of its pseudo-elements, in stylesheet
+// order. `ancestors` must hold the element's ancestors.
+fn matching_rules<'a>(
+    elem: &ElementRef,
+    pseudo_element: Option<PseudoElement>,
+    index: &RuleIndex<'a>,
+    ancestors: &AncestorFilter,
+) -> Vec<MatchedRule<'a>> {
+    let mut matched: Vec<(usize, MatchedRule)> = index
+        .candidates(elem.data)
+        .filter(|candidate| {
+            candidate.selector.pseudo_element() == pseudo_element
+                && ancestors.might_contain_all(&candidate.ancestor_hashes)
+                && matches(elem, candidate.selector)
+        })
+        .map(|candidate| (candidate.position, (candidate.specificity, candidate.rule)))
+        .collect();
+    // A rule counts once, with the specificity of its most specific matching selector.
+    matched.sort_by_key(|&(position, (specificity, _))| (position, Reverse(specificity)));
+    matched.dedup_by_key(|&mut (position, _)| position);
+    matched.into_iter().map(|(_, rule)| rule).collect()
+}
+
+// Selector matching:
+fn matches(elem: &ElementRef, selector: &Selector) -> bool {
+    matches_relative(elem, selector, None)
+}
+
+// Match a selector whose leftmost part, if `anchor` is given, must also be related to the anchor
+// element by the combinator. This is how the selectors in `:has()` are matched.
+fn matches_relative(
+    elem: &ElementRef,
+    selector: &Selector,
+    anchor: Option<(&ElementRef, Combinator)>,
+) -> bool {
+    match selector {
+        Selector::Simple(simple_selector) => {
+            matches_simple_selector(elem, simple_selector)
+                && anchor.is_none_or(|(anchor, combinator)| {
+                    related(elem, combinator, |other| {
+                        std::ptr::eq(other.node, anchor.node)
+                    })
+                })
+        }
+        Selector::Complex(left, combinator, right) => {
+            matches_simple_selector(elem, right)
+                && related(elem, *combinator, |other| {
+                    matches_relative(other, left, anchor)
+                })
+        }
+    }
+}
+
+// Is there an element related to `elem` as `combinator` says for which `test` is true?
+fn related(elem: &ElementRef, combinator: Combinator, test: impl Fn(&ElementRef) -> bool) -> bool {
+    match combinator {
+        Combinator::Descendant => elem.ancestors().any(test),
+        Combinator::Child => elem.parent.is_some_and(test),
+        Combinator::NextSibling => elem
+            .previous_siblings()
+            .next()
+            .is_some_and(|sibling| test(&sibling)),
+        Combinator::SubsequentSibling => elem.previous_siblings().any(|sibling| test(&sibling)),
+    }
+}
+
+fn matches_simple_selector(elem: &ElementRef, selector: &SimpleSelector) -> bool {
+    // Check type selector
+    if selector
+        .tag_name
+        .iter()
+        .any(|name| elem.data.tag_name != *name)
+    {
+        return false;
+    }
+
+    // Check ID selector
+    if selector.id.iter().any(|id| elem.data.id() != Some(id)) {
+        return false;
+    }
+
+    // Check class selectors
+    let elem_classes = elem.data.classes();
+    if selector
+        .class
+        .iter()
+        .any(|class| !elem_classes.contains(&**class))
+    {
+        return false;
+    }
+
+    // Check attribute selectors
+    if !selector
+        .attributes
+        .iter()
+        .all(|attribute| matches_attribute(elem.data, attribute))
+    {
+        return false;
+    }
+
+    // Check pseudo-classes
+    if !selector
+        .pseudo_classes
+        .iter()
+        .all(|pseudo_class| matches_pseudo_class(elem, pseudo_class))
+    {
+        return false;
+    }
+
+    // We didn't find any non-matching selector components.
+    true
+}
+
+fn matches_pseudo_class(elem: &ElementRef, pseudo_class: &PseudoClass) -> bool {
+    let matches_any = |elem: &ElementRef, selectors: &[Selector]| {
+        selectors.iter().any(|selector| matches(elem, selector))
+    };
+    match pseudo_class {
+        PseudoClass::Root => elem.parent.is_none(),
+        // Comments don't count.
+        PseudoClass::Empty => elem
+            .node
+            .children
+            .iter()
+            .all(|child| !matches!(child.node_type, NodeType::Element(_) | NodeType::Text(_))),
+        PseudoClass::FirstChild => elem.previous_siblings().next().is_none(),
+        PseudoClass::LastChild => elem.next_siblings().next().is_none(),
+        PseudoClass::OnlyChild => {
+            elem.previous_siblings().next().is_none() && elem.next_siblings().next().is_none()
+        }
+        PseudoClass::NthChild(nth, None) => nth.matches(elem.previous_siblings().count() + 1),
+        PseudoClass::NthChild(nth, Some(selectors)) => {
+            matches_any(elem, selectors)
+                && nth.matches(
+                    elem.previous_siblings()
+                        .filter(|sibling| matches_any(sibling, selectors))
+                        .count()
+                        + 1,
+                )
+        }
+        PseudoClass::NthOfType(nth) => nth.matches(
+            elem.previous_siblings()
+                .filter(|sibling| sibling.data.tag_name == elem.data.tag_name)
+                .count()
+                + 1,
+        ),
+        PseudoClass::Not(selectors) => !matches_any(elem, selectors),
+        PseudoClass::Is(selectors) | PseudoClass::Where(selectors) => matches_any(elem, selectors),
+        PseudoClass::Has(selectors) => selectors.iter().any(|relative| has(elem, relative)),
+    }
+}
+
+// Does any element relative to `anchor` match? Descendant and child selectors can only match
+// inside `anchor`; sibling selectors can only match in or after its following siblings.
+fn has(anchor: &ElementRef, relative: &RelativeSelector) -> bool {
+    fn any_in_subtree(elem: &ElementRef, test: &dyn Fn(&ElementRef) -> bool) -> bool {
+        elem.children()
+            .any(|child| test(&child) || any_in_subtree(&child, test))
+    }
+    let test = |elem: &ElementRef| {
+        matches_relative(
+            elem,
+            &relative.selector,
+            Some((anchor, relative.combinator)),
+        )
+    };
+    match relative.combinator {
+        Combinator::Descendant | Combinator::Child => any_in_subtree(anchor, &test),
+        Combinator::NextSibling | Combinator::SubsequentSibling => anchor
+            .next_siblings()
+            .any(|sibling| test(&sibling) || any_in_subtree(&sibling, &test)),
+    }
+}
+
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+    let Some(value) = elem.attrs.attrs.get(&selector.name) else {
+        return false;
+    };
+    let Some((operator, expected)) = &selector.matcher else {
+        return true;
+    };
+    let (value, expected) = if selector.case_insensitive {
+        (value.to_ascii_lowercase(), expected.to_ascii_lowercase())
+    } else {
+        (value.clone(), expected.clone())
+    };
+    match operator {
+        AttributeOperator::Equals => value == expected,
+        AttributeOperator::DashMatch => {
+            value == expected || value.starts_with(&format!("{}-", expected))
+        }
+        // An empty string never matches these, rather than matching everything.
+        _ if expected.is_empty() => false,
+        AttributeOperator::Includes => value.split_ascii_whitespace().any(|v| v == expected),
+        AttributeOperator::Prefix => value.starts_with(&expected),
+        AttributeOperator::Suffix => value.ends_with(&expected),
+        AttributeOperator::Substring => value.contains(&expected),
+    }
+}
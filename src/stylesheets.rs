@@ -0,0 +1,132 @@
+// Finding the stylesheets a document refers to: the contents of `<style>` elements and the
+// targets of `<link rel="stylesheet">` elements, in document order.
+//
+// https://html.spec.whatwg.org/multipage/semantics.html#the-style-element
+// https://html.spec.whatwg.org/multipage/links.html#link-type-stylesheet
+
+use crate::html::{Node, NodeType};
+use crate::parser::{ParseError, Parser};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum StyleSource {
+    // The text of a `<style>` element.
+    Inline(String),
+    // The file a `<link rel="stylesheet">` points to.
+    Link(PathBuf),
+}
+
+// Collect the stylesheets referenced by a document. Relative `href`s are resolved against
+// `base`, the directory containing the document.
+pub(crate) fn style_sources(root: &Node, base: &Path) -> Vec<StyleSource> {
+    let mut sources = Vec::new();
+    collect(root, base, &mut sources);
+    sources
+}
+
+fn collect(node: &Node, base: &Path, sources: &mut Vec<StyleSource>) {
+    let data = match &node.node_type {
+        NodeType::Element(data) => data,
+        _ => return,
+    };
+    let attr = |name: &str| data.attrs.attrs.get(name).map(String::as_str);
+    match &*data.tag_name {
+        "style" if is_css(attr("type")) => {
+            let text = node
+                .children
+                .iter()
+                .filter_map(|child| match &child.node_type {
+                    NodeType::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            sources.push(StyleSource::Inline(text));
+        }
+        "link" if is_stylesheet_link(attr("rel")) && is_css(attr("type")) => {
+            if let Some(path) = attr("href").and_then(|href| resolve(href, base)) {
+                sources.push(StyleSource::Link(path));
+            }
+        }
+        // Templates are inert, so styles inside them don't apply.
+        "template" => {}
+        _ => {
+            for child in &node.children {
+                collect(child, base, sources);
+            }
+        }
+    }
+}
+
+// The errors in a document's `style` attributes, each with the tag name of its element, in
+// document order. Styling leaves the invalid declarations out by itself; this is for reporting
+// them.
+pub(crate) fn style_attribute_errors(root: &Node) -> Vec<(String, ParseError)> {
+    let mut errors = Vec::new();
+    collect_style_attribute_errors(root, &mut errors);
+    errors
+}
+
+fn collect_style_attribute_errors(node: &Node, errors: &mut Vec<(String, ParseError)>) {
+    let NodeType::Element(data) = &node.node_type else {
+        return;
+    };
+    if let Some(style) = data.attrs.attrs.get("style") {
+        let mut parser = Parser::new(style.clone());
+        parser.parse_style_attribute();
+        let tag_name = &data.tag_name;
+        errors.extend(
+            parser
+                .diagnostics
+                .into_iter()
+                .map(|e| (tag_name.clone(), e)),
+        );
+    }
+    for child in &node.children {
+        collect_style_attribute_errors(child, errors);
+    }
+}
+
+// `rel` is a set of space-separated keywords. Alternate stylesheets aren't applied unless the
+// user picks them, which we have no way to do.
+fn is_stylesheet_link(rel: Option<&str>) -> bool {
+    let keywords = rel.unwrap_or("").split_ascii_whitespace();
+    let has = |keyword: &str| keywords.clone().any(|k| k.eq_ignore_ascii_case(keyword));
+    has("stylesheet") && !has("alternate")
+}
+
+// A missing or empty `type` means CSS.
+fn is_css(kind: Option<&str>) -> bool {
+    match kind.map(str::trim) {
+        None | Some("") => true,
+        Some(kind) => kind.eq_ignore_ascii_case("text/css"),
+    }
+}
+
+// Turn an `href` into a local path. Only relative references and `file:` URLs can be loaded;
+// anything with another scheme is skipped.
+fn resolve(href: &str, base: &Path) -> Option<PathBuf> {
+    let href = href.trim();
+    let href = href.split(['?', '#']).next().unwrap_or("");
+    let path = match href.strip_prefix("file://") {
+        Some(path) => path,
+        None if href.starts_with("//") || has_scheme(href) => return None,
+        None => href,
+    };
+    if path.is_empty() {
+        return None;
+    }
+    Some(base.join(path))
+}
+
+// https://url.spec.whatwg.org/#scheme-state
+fn has_scheme(href: &str) -> bool {
+    match href.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,974 @@
+// Tree construction for HTML documents, after the HTML Living Standard:
+// https://html.spec.whatwg.org/multipage/parsing.html#tree-construction
+//
+// This covers the insertion modes needed for ordinary documents: implied `html`, `head` and
+// `body` elements, optional end tags and recovery from mismatched end tags. Tables are handled
+// inside "in body" with implied `tbody`/`tr` elements rather than the full table modes, and
+// misnested formatting elements are not reparented.
+
+use crate::html::{
+    comment, elem, text, AttrMap, DoctypeData, Document, Node, NodeType, QuirksMode,
+};
+use crate::html_tokenizer::{Doctype, State, Tag, Token, Tokenizer};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    // Inside an element whose contents are text only, such as `title` or `script`.
+    Text,
+    AfterBody,
+    AfterAfterBody,
+}
+
+// Elements that are closed by any end tag that closes an ancestor, and by `</p>`-style implied
+// end tags.
+const IMPLIED_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+// Elements that terminate the search for an element "in scope".
+const SCOPE_BOUNDARIES: &[&str] = &[
+    "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+];
+
+const TABLE_SCOPE_BOUNDARIES: &[&str] = &["html", "table", "template"];
+
+// https://html.spec.whatwg.org/multipage/parsing.html#special
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "bgsound",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "keygen",
+    "li",
+    "link",
+    "listing",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "ol",
+    "p",
+    "param",
+    "plaintext",
+    "pre",
+    "script",
+    "search",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+    "wbr",
+    "xmp",
+];
+
+// Start tags that close an open `p` element.
+const CLOSES_P: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "header",
+    "hgroup",
+    "hr",
+    "listing",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+// End tags that close the matching element, and everything opened inside it.
+const BLOCK_END_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "button",
+    "center",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "header",
+    "hgroup",
+    "listing",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "pre",
+    "search",
+    "section",
+    "summary",
+    "ul",
+];
+
+// Elements that never have contents, so they have no end tag.
+// https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+const TABLE_SECTIONS: &[&str] = &["tbody", "thead", "tfoot"];
+
+// Elements whose contents are read as text until their end tag. Character references are
+// decoded in `title` and `textarea` (RCDATA) but not in the others (raw text).
+const TEXT_ONLY_ELEMENTS: &[&str] = &[
+    "title", "textarea", "style", "script", "xmp", "iframe", "noembed", "noframes",
+];
+
+// Elements that belong in `head` even when they appear after it.
+const HEAD_ELEMENTS: &[&str] = &[
+    "base", "basefont", "bgsound", "link", "meta", "noframes", "script", "style", "template",
+    "title",
+];
+
+// DOCTYPE public identifiers that put the document in quirks mode, compared by prefix.
+const QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19970916::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+// HTML 4.01 DTDs: quirks mode without a system identifier, limited quirks mode with one.
+const HTML4_TRANSITIONAL_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "-//w3c//dtd html 4.01 frameset//",
+    "-//w3c//dtd html 4.01 transitional//",
+];
+
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "-//w3c//dtd xhtml 1.0 frameset//",
+    "-//w3c//dtd xhtml 1.0 transitional//",
+];
+
+pub(crate) struct TreeBuilder<'a> {
+    tokenizer: Tokenizer<'a>,
+    mode: InsertionMode,
+    // The mode to return to when a text-only element is closed.
+    original_mode: InsertionMode,
+    // Elements that have been opened but not closed yet, innermost last. Each is attached to its
+    // parent when it is popped.
+    open_elements: Vec<Node>,
+    // Whether `head` was pushed back onto the stack for an element found after it.
+    head_reopened: bool,
+    // Children of the document other than the `html` element: the DOCTYPE and comments.
+    document_children: Vec<Node>,
+    // Where the `html` element goes among `document_children`.
+    html_index: usize,
+    // Comments after `</body>`, waiting for `body` to be closed so they can follow it in `html`.
+    comments_after_body: Vec<Node>,
+    quirks_mode: QuirksMode,
+}
+
+impl<'a> TreeBuilder<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        TreeBuilder {
+            tokenizer: Tokenizer::new(input),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            open_elements: Vec::new(),
+            head_reopened: false,
+            document_children: Vec::new(),
+            html_index: 0,
+            comments_after_body: Vec::new(),
+            quirks_mode: QuirksMode::NoQuirks,
+        }
+    }
+
+    // Run the tokenizer to completion and return the document.
+    pub(crate) fn build(mut self) -> Document {
+        loop {
+            self.tokenizer.set_cdata_allowed(self.in_foreign_content());
+            let token = self.tokenizer.next_token();
+            let eof = token == Token::Eof;
+            self.process(token);
+            if eof {
+                break;
+            }
+        }
+        while self.open_elements.len() > 1 {
+            self.pop();
+        }
+        let html = self
+            .open_elements
+            .pop()
+            .expect("EOF always creates an html element");
+        let mut children = self.document_children;
+        children.insert(self.html_index, html);
+        Document {
+            children,
+            mode: self.quirks_mode,
+        }
+    }
+
+    fn process(&mut self, token: Token) {
+        match self.mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::AfterBody | InsertionMode::AfterAfterBody => self.after_body(token),
+        }
+    }
+
+    // Switch to `mode` and process `token` again.
+    fn reprocess_in(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.process(token);
+    }
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => {
+                if let (_, Some(data)) = split_leading_whitespace(data) {
+                    self.reprocess_in(InsertionMode::BeforeHtml, Token::Text(data));
+                }
+            }
+            Token::Comment(data) => self.document_children.push(comment(data)),
+            Token::Doctype(doctype) => {
+                self.quirks_mode = quirks_mode(&doctype);
+                self.document_children.push(Node {
+                    children: Vec::new(),
+                    node_type: NodeType::Doctype(DoctypeData {
+                        name: doctype.name.unwrap_or_default(),
+                        public_id: doctype.public_id.unwrap_or_default(),
+                        system_id: doctype.system_id.unwrap_or_default(),
+                    }),
+                });
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                // A document without a DOCTYPE is rendered in quirks mode.
+                self.quirks_mode = QuirksMode::Quirks;
+                self.reprocess_in(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => {
+                if let (_, Some(data)) = split_leading_whitespace(data) {
+                    self.insert_html(AttrMap::from(vec![]));
+                    self.reprocess_in(InsertionMode::BeforeHead, Token::Text(data));
+                }
+            }
+            Token::Comment(data) => self.document_children.push(comment(data)),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) if tag.name == "html" => {
+                self.insert_html(AttrMap::from(tag.attrs));
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag(tag) if !matches!(&*tag.name, "head" | "body" | "html" | "br") => {}
+            token => {
+                self.insert_html(AttrMap::from(vec![]));
+                self.reprocess_in(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => {
+                if let (_, Some(data)) = split_leading_whitespace(data) {
+                    self.insert_element(implied("head"));
+                    self.reprocess_in(InsertionMode::InHead, Token::Text(data));
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "head" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag(tag) if !matches!(&*tag.name, "head" | "body" | "html" | "br") => {}
+            token => {
+                self.insert_element(implied("head"));
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => {
+                let (whitespace, data) = split_leading_whitespace(data);
+                self.append_text(whitespace);
+                if let Some(data) = data {
+                    self.pop();
+                    self.reprocess_in(InsertionMode::AfterHead, Token::Text(data));
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag)
+                if matches!(
+                    &*tag.name,
+                    "base" | "basefont" | "bgsound" | "link" | "meta"
+                ) =>
+            {
+                self.insert_element(tag)
+            }
+            Token::StartTag(tag)
+                if matches!(&*tag.name, "title" | "noframes" | "style" | "script") =>
+            {
+                self.insert_text_only_element(tag)
+            }
+            Token::StartTag(tag) if matches!(&*tag.name, "noscript" | "template") => {
+                self.insert_element(tag)
+            }
+            Token::EndTag(tag) if matches!(&*tag.name, "noscript" | "template") => {
+                if self.current_tag_name() == tag.name {
+                    self.pop();
+                }
+            }
+            Token::StartTag(tag) if tag.name == "head" => {}
+            Token::EndTag(tag) if tag.name == "head" => {
+                self.pop();
+                self.mode = InsertionMode::AfterHead;
+            }
+            Token::EndTag(tag) if !matches!(&*tag.name, "body" | "html" | "br") => {}
+            token => {
+                // Close `head` (and anything left open inside it) and move on.
+                self.pop_until(&["head"]);
+                self.reprocess_in(InsertionMode::AfterHead, token);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => {
+                let (whitespace, data) = split_leading_whitespace(data);
+                self.append_text(whitespace);
+                if let Some(data) = data {
+                    self.insert_element(implied("body"));
+                    self.reprocess_in(InsertionMode::InBody, Token::Text(data));
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "body" => {
+                self.insert_element(tag);
+                self.mode = InsertionMode::InBody;
+            }
+            Token::StartTag(tag) if HEAD_ELEMENTS.contains(&&*tag.name) => {
+                // Put the element in `head` after all.
+                self.reopen_head();
+                self.in_head(Token::StartTag(tag));
+                self.close_reopened_head();
+            }
+            Token::StartTag(tag) if tag.name == "head" => {}
+            Token::EndTag(tag) if !matches!(&*tag.name, "body" | "html" | "br") => {}
+            token => {
+                self.insert_element(implied("body"));
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => self.append_text(data),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::Eof => {}
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: Tag) {
+        let name = tag.name.as_str();
+        match name {
+            "html" | "body" => {
+                // Copy over attributes the existing element doesn't have yet.
+                let index = if name == "html" { 0 } else { 1 };
+                if let Some(NodeType::Element(data)) = self
+                    .open_elements
+                    .get_mut(index)
+                    .map(|node| &mut node.node_type)
+                {
+                    if data.tag_name == name {
+                        for (key, value) in tag.attrs {
+                            data.attrs.attrs.entry(key).or_insert(value);
+                        }
+                    }
+                }
+            }
+            _ if TEXT_ONLY_ELEMENTS.contains(&name) => self.insert_text_only_element(tag),
+            _ if HEADINGS.contains(&name) => {
+                self.close_p_in_button_scope();
+                if HEADINGS.contains(&self.current_tag_name()) {
+                    self.pop();
+                }
+                self.insert_element(tag);
+            }
+            "plaintext" => {
+                // Everything after `<plaintext>` is text; there is no way to close it.
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+                self.tokenizer.set_state(State::Plaintext);
+            }
+            // Quirks mode lets a table sit inside a paragraph.
+            "table" if self.quirks_mode == QuirksMode::Quirks => self.insert_element(tag),
+            _ if CLOSES_P.contains(&name) => {
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+            }
+            "li" | "dd" | "dt" => {
+                // Close the previous list item, unless something else is in the way.
+                let closes: &[&str] = if name == "li" { &["li"] } else { &["dd", "dt"] };
+                for i in (0..self.open_elements.len()).rev() {
+                    let open = self.tag_name_at(i);
+                    if closes.contains(&open) {
+                        let open = open.to_string();
+                        self.generate_implied_end_tags(Some(&open));
+                        self.pop_until(&[&open]);
+                        break;
+                    }
+                    if SPECIAL_ELEMENTS.contains(&open) && !matches!(open, "address" | "div" | "p")
+                    {
+                        break;
+                    }
+                }
+                self.close_p_in_button_scope();
+                self.insert_element(tag);
+            }
+            "button" => {
+                if self.has_in_scope(&["button"], SCOPE_BOUNDARIES) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.insert_element(tag);
+            }
+            "option" => {
+                if self.current_tag_name() == "option" {
+                    self.pop();
+                }
+                self.insert_element(tag);
+            }
+            "optgroup" => {
+                if self.current_tag_name() == "option" {
+                    self.pop();
+                }
+                if self.current_tag_name() == "optgroup" {
+                    self.pop();
+                }
+                self.insert_element(tag);
+            }
+            "caption" | "colgroup" | "tbody" | "thead" | "tfoot" => {
+                self.close_table_part(&["td", "th", "tr", "tbody", "thead", "tfoot", "caption"]);
+                self.insert_element(tag);
+            }
+            "tr" => {
+                self.close_table_part(&["td", "th", "tr"]);
+                if self.current_tag_name() == "table" {
+                    self.insert_element(implied("tbody"));
+                }
+                self.insert_element(tag);
+            }
+            "td" | "th" => {
+                self.close_table_part(&["td", "th"]);
+                if self.current_tag_name() == "table" {
+                    self.insert_element(implied("tbody"));
+                }
+                if TABLE_SECTIONS.contains(&self.current_tag_name()) {
+                    self.insert_element(implied("tr"));
+                }
+                self.insert_element(tag);
+            }
+            _ => self.insert_element(tag),
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) {
+        let name = tag.name.as_str();
+        match name {
+            "body" | "html" => {
+                if self.has_in_scope(&["body"], SCOPE_BOUNDARIES) {
+                    self.mode = InsertionMode::AfterBody;
+                    if name == "html" {
+                        self.reprocess_in(InsertionMode::AfterBody, Token::EndTag(tag));
+                    }
+                }
+            }
+            "p" => {
+                if !self.has_in_scope(&["p"], &button_scope()) {
+                    // A stray `</p>` produces an empty paragraph, as in browsers.
+                    self.insert_element(implied("p"));
+                }
+                self.close_p_in_button_scope();
+            }
+            "li" => {
+                if self.has_in_scope(&["li"], &list_item_scope()) {
+                    self.generate_implied_end_tags(Some("li"));
+                    self.pop_until(&["li"]);
+                }
+            }
+            "dd" | "dt" => {
+                if self.has_in_scope(&[name], SCOPE_BOUNDARIES) {
+                    self.generate_implied_end_tags(Some(name));
+                    self.pop_until(&[name]);
+                }
+            }
+            _ if HEADINGS.contains(&name) => {
+                if self.has_in_scope(HEADINGS, SCOPE_BOUNDARIES) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(HEADINGS);
+                }
+            }
+            _ if BLOCK_END_TAGS.contains(&name) => {
+                if self.has_in_scope(&[name], SCOPE_BOUNDARIES) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&[name]);
+                }
+            }
+            "table" | "caption" | "colgroup" | "tbody" | "thead" | "tfoot" | "tr" | "td" | "th" => {
+                if self.has_in_scope(&[name], TABLE_SCOPE_BOUNDARIES) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&[name]);
+                }
+            }
+            "br" => {
+                // `</br>` is treated as `<br>`.
+                self.insert_element(implied("br"));
+            }
+            _ => self.any_other_end_tag(name),
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody:any-other-end-tag
+    fn any_other_end_tag(&mut self, name: &str) {
+        for i in (1..self.open_elements.len()).rev() {
+            let open = self.tag_name_at(i);
+            if open == name {
+                self.generate_implied_end_tags(Some(name));
+                while self.open_elements.len() > i {
+                    self.pop();
+                }
+                return;
+            }
+            if SPECIAL_ELEMENTS.contains(&open) {
+                // Ignore the end tag rather than close an unrelated special element.
+                return;
+            }
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            // The contents of a text-only element are kept exactly, whitespace and all.
+            Token::Text(data) => self.append_text(data),
+            Token::EndTag(tag) if tag.name == self.current_tag_name() => {
+                self.pop();
+                self.mode = self.original_mode;
+                self.close_reopened_head();
+            }
+            Token::Eof => {
+                self.pop();
+                self.close_reopened_head();
+                self.reprocess_in(self.original_mode, Token::Eof);
+            }
+            // Markup inside a text-only element is not allowed to create elements.
+            _ => {}
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Text(data) => {
+                // Whitespace still goes into `body`, which is the current node.
+                let (whitespace, data) = split_leading_whitespace(data);
+                self.append_text(whitespace);
+                if let Some(data) = data {
+                    self.reprocess_in(InsertionMode::InBody, Token::Text(data));
+                }
+            }
+            Token::Comment(data) => {
+                if self.mode == InsertionMode::AfterAfterBody {
+                    self.document_children.push(comment(data));
+                } else if self.open_elements.len() == 1 {
+                    self.open_elements[0].children.push(comment(data));
+                } else {
+                    // Comments after `</body>` go at the end of the `html` element, after
+                    // `body`. That is still open, since more content can follow, so they wait.
+                    self.comments_after_body.push(comment(data));
+                }
+            }
+            Token::Doctype(_) | Token::Eof => {}
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "html" => self.mode = InsertionMode::AfterAfterBody,
+            token => self.reprocess_in(InsertionMode::InBody, token),
+        }
+    }
+
+    // Create the root `html` element.
+    fn insert_html(&mut self, attrs: AttrMap) {
+        self.html_index = self.document_children.len();
+        self.open_elements
+            .push(elem("html".to_string(), attrs, Vec::new()));
+    }
+
+    // Open a new element as a child of the current node. Void elements are closed straight away,
+    // whether or not the tag was written self-closing; on any other element `/>` is ignored, as
+    // it is in browsers.
+    fn insert_element(&mut self, tag: Tag) {
+        let void = VOID_ELEMENTS.contains(&&*tag.name);
+        self.open_elements
+            .push(elem(tag.name, AttrMap::from(tag.attrs), Vec::new()));
+        if void {
+            self.pop();
+        }
+    }
+
+    fn insert_comment(&mut self, data: String) {
+        let parent = self.open_elements.last_mut().expect("no open element");
+        parent.children.push(comment(data));
+    }
+
+    fn insert_text_only_element(&mut self, tag: Tag) {
+        let state = match &*tag.name {
+            "title" | "textarea" => State::Rcdata,
+            "script" => State::ScriptData,
+            _ => State::Rawtext,
+        };
+        self.tokenizer.set_state(state);
+        self.insert_element(tag);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    // Close the current node, attaching it to its parent.
+    fn pop(&mut self) {
+        if let Some(node) = self.open_elements.pop() {
+            match self.open_elements.last_mut() {
+                Some(parent) => parent.children.push(node),
+                // Never drop the root; put it back.
+                None => self.open_elements.push(node),
+            }
+            if self.open_elements.len() == 1 && !self.comments_after_body.is_empty() {
+                let comments = std::mem::take(&mut self.comments_after_body);
+                self.open_elements[0].children.extend(comments);
+            }
+        }
+    }
+
+    // Pop elements until one of the given elements has been popped.
+    fn pop_until(&mut self, names: &[&str]) {
+        while self.open_elements.len() > 1 {
+            let done = names.contains(&self.current_tag_name());
+            self.pop();
+            if done {
+                break;
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#generate-implied-end-tags
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        loop {
+            let current = self.current_tag_name();
+            if Some(current) == except || !IMPLIED_END_TAGS.contains(&current) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.has_in_scope(&["p"], &button_scope()) {
+            self.generate_implied_end_tags(Some("p"));
+            self.pop_until(&["p"]);
+        }
+    }
+
+    // Close the innermost of `names` if it is open inside the current table.
+    fn close_table_part(&mut self, names: &[&str]) {
+        if self.has_in_scope(names, TABLE_SCOPE_BOUNDARIES) {
+            self.generate_implied_end_tags(None);
+            self.pop_until(names);
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-scope
+    fn has_in_scope(&self, names: &[&str], boundaries: &[&str]) -> bool {
+        for i in (0..self.open_elements.len()).rev() {
+            let open = self.tag_name_at(i);
+            if names.contains(&open) {
+                return true;
+            }
+            if boundaries.contains(&open) {
+                return false;
+            }
+        }
+        false
+    }
+
+    // Push `head` back onto the stack so an element found after it can be inserted there.
+    fn reopen_head(&mut self) {
+        let html = &mut self.open_elements[0];
+        if html.children.last().and_then(Node::tag_name) == Some("head") {
+            let head = html.children.pop().unwrap();
+            self.open_elements.push(head);
+            self.head_reopened = true;
+        }
+    }
+
+    // Close `head` again once the element inserted into it is finished.
+    fn close_reopened_head(&mut self) {
+        if self.head_reopened && self.current_tag_name() == "head" {
+            self.pop();
+            self.head_reopened = false;
+        }
+    }
+
+    // Append text to the current node, merging it with a preceding text node.
+    fn append_text(&mut self, data: String) {
+        if data.is_empty() {
+            return;
+        }
+        let parent = self.open_elements.last_mut().expect("no open element");
+        match parent.children.last_mut() {
+            Some(Node {
+                node_type: NodeType::Text(existing),
+                ..
+            }) => existing.push_str(&data),
+            _ => parent.children.push(text(data)),
+        }
+    }
+
+    // Whether we are inside an `svg` or `math` element. HTML integration points such as
+    // `foreignObject` are not distinguished.
+    fn in_foreign_content(&self) -> bool {
+        self.open_elements
+            .iter()
+            .any(|node| matches!(node.tag_name(), Some("svg" | "math")))
+    }
+
+    fn current_tag_name(&self) -> &str {
+        self.open_elements
+            .last()
+            .and_then(Node::tag_name)
+            .unwrap_or("")
+    }
+
+    fn tag_name_at(&self, index: usize) -> &str {
+        self.open_elements[index].tag_name().unwrap_or("")
+    }
+}
+
+// Pick the document mode from its DOCTYPE.
+// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+fn quirks_mode(doctype: &Doctype) -> QuirksMode {
+    let public_id = doctype.public_id.as_deref().map(str::to_ascii_lowercase);
+    let system_id = doctype.system_id.as_deref().map(str::to_ascii_lowercase);
+    let public_starts_with = |prefixes: &[&str]| {
+        public_id
+            .as_deref()
+            .is_some_and(|id| prefixes.iter().any(|prefix| id.starts_with(prefix)))
+    };
+
+    if doctype.force_quirks
+        || doctype.name.as_deref() != Some("html")
+        || matches!(
+            public_id.as_deref(),
+            Some(
+                "-//w3o//dtd w3 html strict 3.0//en//"
+                    | "-/w3c/dtd html 4.0 transitional/en"
+                    | "html"
+            )
+        )
+        || system_id.as_deref()
+            == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || public_starts_with(QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_none() && public_starts_with(HTML4_TRANSITIONAL_PUBLIC_ID_PREFIXES))
+    {
+        QuirksMode::Quirks
+    } else if public_starts_with(LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_some() && public_starts_with(HTML4_TRANSITIONAL_PUBLIC_ID_PREFIXES))
+    {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+fn button_scope() -> Vec<&'static str> {
+    [SCOPE_BOUNDARIES, &["button"]].concat()
+}
+
+fn list_item_scope() -> Vec<&'static str> {
+    [SCOPE_BOUNDARIES, &["ol", "ul"]].concat()
+}
+
+// A start tag for an element the parser inserts without it appearing in the source.
+fn implied(name: &str) -> Tag {
+    Tag {
+        name: name.to_string(),
+        ..Default::default()
+    }
+}
+
+// Split text into its leading ASCII whitespace and the rest, if there is any.
+fn split_leading_whitespace(mut data: String) -> (String, Option<String>) {
+    let end = data.len()
+        - data
+            .trim_start_matches(|c: char| c.is_ascii_whitespace())
+            .len();
+    if end == data.len() {
+        (data, None)
+    } else {
+        let rest = data.split_off(end);
+        (data, Some(rest))
+    }
+}